@@ -0,0 +1,194 @@
+use anyhow::{bail, Context};
+use serde_bytes::ByteBuf;
+use serde_derive::{Deserialize, Serialize};
+
+use super::{NodeId, NodeInfo};
+use crate::peer::PeerAddresses;
+
+/// A KRPC message, the bencoded dictionary exchanged between DHT nodes.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct KrpcMessage {
+    /// Transaction id, echoed back in the response.
+    pub t: ByteBuf,
+
+    /// Message type: `q` for queries, `r` for responses and `e` for errors.
+    pub y: String,
+
+    /// Query method name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub q: Option<String>,
+
+    /// Query arguments.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub a: Option<QueryArgs>,
+
+    /// Response values.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub r: Option<ResponseValues>,
+
+    /// Error code and message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e: Option<(i64, String)>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct QueryArgs {
+    /// Id of the querying node.
+    pub id: ByteBuf,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<ByteBuf>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info_hash: Option<ByteBuf>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+
+    /// Token from a previous `get_peers` response, required by `announce_peer`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<ByteBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ResponseValues {
+    /// Id of the responding node.
+    pub id: ByteBuf,
+
+    /// Compact node info of nodes close to the queried target.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nodes: Option<ByteBuf>,
+
+    /// Compact addresses of peers for the queried info hash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<ByteBuf>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<ByteBuf>,
+}
+
+impl KrpcMessage {
+    pub fn query(transaction_id: &[u8], method: &str, args: QueryArgs) -> Self {
+        Self {
+            t: ByteBuf::from(transaction_id),
+            y: "q".to_string(),
+            q: Some(method.to_string()),
+            a: Some(args),
+            r: None,
+            e: None,
+        }
+    }
+
+    pub fn from_bytes(packet: &[u8]) -> anyhow::Result<Self> {
+        serde_bencode::from_bytes(packet).context("Failed to parse KRPC message")
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        serde_bencode::to_bytes(self).context("Failed to encode KRPC message")
+    }
+
+    /// The response values, failing for error messages and queries.
+    pub fn into_response(self) -> anyhow::Result<ResponseValues> {
+        match self.y.as_str() {
+            "r" => self.r.context("KRPC response has no values"),
+            "e" => match self.e {
+                Some((code, message)) => bail!("DHT node returned error {}: {}", code, message),
+                None => bail!("DHT node returned an error"),
+            },
+            other => bail!("Expected a KRPC response, got message type {:?}", other),
+        }
+    }
+}
+
+impl ResponseValues {
+    pub fn node_id(&self) -> anyhow::Result<NodeId> {
+        let id: [u8; 20] = self
+            .id
+            .as_slice()
+            .try_into()
+            .context("Node id is not 20 bytes")?;
+        Ok(NodeId(id))
+    }
+
+    /// Nodes in the response, empty if there are none or the list is malformed.
+    pub fn nodes(&self) -> Vec<NodeInfo> {
+        self.nodes
+            .as_ref()
+            .and_then(|nodes| NodeInfo::from_compact(nodes))
+            .unwrap_or_default()
+    }
+
+    /// Peers in a `get_peers` response, skipping malformed entries.
+    pub fn peers(&self) -> PeerAddresses {
+        PeerAddresses(
+            self.values
+                .iter()
+                .flatten()
+                .filter_map(|value| PeerAddresses::from_compact(value))
+                .flat_map(|peers| peers.0)
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Example packets from BEP 5
+    const PING_QUERY: &[u8] = b"d1:ad2:id20:abcdefghij0123456789e1:q4:ping1:t2:aa1:y1:qe";
+    const PING_RESPONSE: &[u8] = b"d1:rd2:id20:mnopqrstuvwxyz123456e1:t2:aa1:y1:re";
+    const GET_PEERS_RESPONSE: &[u8] =
+        b"d1:rd2:id20:abcdefghij01234567895:token8:aoeusnth6:valuesl6:axje.u6:idhtnmee1:t2:aa1:y1:re";
+    const ERROR: &[u8] = b"d1:eli201e23:A Generic Error Ocurrede1:t2:aa1:y1:ee";
+
+    #[test]
+    fn test_encode_ping_query() {
+        let message = KrpcMessage::query(
+            b"aa",
+            "ping",
+            QueryArgs {
+                id: ByteBuf::from(&b"abcdefghij0123456789"[..]),
+                ..Default::default()
+            },
+        );
+        assert_eq!(message.to_bytes().unwrap(), PING_QUERY);
+        assert_eq!(KrpcMessage::from_bytes(PING_QUERY).unwrap(), message);
+    }
+
+    #[test]
+    fn test_parse_ping_response() {
+        let response = KrpcMessage::from_bytes(PING_RESPONSE)
+            .unwrap()
+            .into_response()
+            .unwrap();
+        assert_eq!(
+            response.node_id().unwrap(),
+            NodeId(*b"mnopqrstuvwxyz123456")
+        );
+    }
+
+    #[test]
+    fn test_parse_get_peers_response() {
+        let response = KrpcMessage::from_bytes(GET_PEERS_RESPONSE)
+            .unwrap()
+            .into_response()
+            .unwrap();
+
+        assert_eq!(response.token.as_ref().unwrap().as_slice(), b"aoeusnth");
+        assert_eq!(
+            response.peers(),
+            PeerAddresses::from_compact(b"axje.uidhtnm").unwrap()
+        );
+        assert!(response.nodes().is_empty());
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let error = KrpcMessage::from_bytes(ERROR)
+            .unwrap()
+            .into_response()
+            .unwrap_err();
+        assert!(error.to_string().contains("201: A Generic Error Ocurred"));
+    }
+}
@@ -0,0 +1,11 @@
+//! Minimal DHT client (BEP 5), used to find peers for torrents without a working tracker.
+//!
+//! Private torrents (BEP 27) must not use it.
+
+mod krpc;
+mod node;
+mod routing;
+
+pub use krpc::{KrpcMessage, QueryArgs, ResponseValues};
+pub use node::{DhtNode, GetPeers, BOOTSTRAP_NODES};
+pub use routing::{NodeId, NodeInfo, RoutingTable, K};
@@ -0,0 +1,472 @@
+use std::collections::{BTreeMap, HashSet};
+use std::net::SocketAddr;
+
+use anyhow::{bail, Context};
+use serde_bytes::ByteBuf;
+use tokio::{
+    net::{lookup_host, ToSocketAddrs, UdpSocket},
+    time::{timeout, Duration, Instant},
+};
+use tracing::{debug, info, instrument};
+
+use super::{KrpcMessage, NodeId, NodeInfo, QueryArgs, ResponseValues, RoutingTable, K};
+use crate::peer::PeerAddresses;
+use crate::torrent::InfoHash;
+
+/// Well known nodes to join the DHT through.
+pub const BOOTSTRAP_NODES: [&str; 3] = [
+    "router.bittorrent.com:6881",
+    "dht.transmissionbt.com:6881",
+    "router.utorrent.com:6881",
+];
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_PACKET_SIZE: usize = 4096;
+
+// Bounds the iterative lookup, each round queries the closest nodes not queried yet
+const MAX_LOOKUP_ROUNDS: usize = 20;
+
+// Number of get_peers queries a lookup keeps in flight at once
+const ALPHA: usize = 3;
+
+/// Result of a `get_peers` query: peers if the node knows any, closer nodes otherwise, and the
+/// token needed to announce to it.
+#[derive(Debug, Clone, Default)]
+pub struct GetPeers {
+    pub peers: PeerAddresses,
+    pub nodes: Vec<NodeInfo>,
+    pub token: Option<Vec<u8>>,
+}
+
+/// A DHT node that only sends queries, it doesn't answer other nodes.
+#[derive(Debug)]
+pub struct DhtNode {
+    id: NodeId,
+    socket: UdpSocket,
+    table: RoutingTable,
+    transaction_id: u16,
+}
+
+impl DhtNode {
+    pub async fn bind(addr: impl ToSocketAddrs) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(addr)
+            .await
+            .context("Failed to bind DHT socket")?;
+        let id = NodeId::random();
+
+        Ok(Self {
+            id,
+            socket,
+            table: RoutingTable::new(id),
+            transaction_id: 0,
+        })
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn routing_table(&self) -> &RoutingTable {
+        &self.table
+    }
+
+    pub fn local_addr(&self) -> anyhow::Result<SocketAddr> {
+        self.socket
+            .local_addr()
+            .context("Failed to get DHT socket address")
+    }
+
+    /// Fills the routing table by asking each bootstrap host for nodes close to our id.
+    #[instrument(skip(self))]
+    pub async fn bootstrap(&mut self, hosts: &[&str]) -> anyhow::Result<()> {
+        for host in hosts {
+            let addrs = match lookup_host(host).await {
+                Ok(addrs) => addrs,
+                Err(e) => {
+                    debug!("Failed to resolve bootstrap node {}: {}", host, e);
+                    continue;
+                }
+            };
+            for addr in addrs.filter(SocketAddr::is_ipv4) {
+                if let Err(e) = self.find_node(addr, self.id).await {
+                    debug!("Bootstrap node {} failed: {:#}", addr, e);
+                }
+            }
+        }
+
+        if self.table.is_empty() {
+            bail!("No bootstrap node answered");
+        }
+        info!("Bootstrapped DHT with {} nodes", self.table.len());
+        Ok(())
+    }
+
+    pub async fn ping(&mut self, addr: SocketAddr) -> anyhow::Result<NodeId> {
+        let response = self.query(addr, "ping", QueryArgs::default()).await?;
+        response.node_id()
+    }
+
+    pub async fn find_node(
+        &mut self,
+        addr: SocketAddr,
+        target: NodeId,
+    ) -> anyhow::Result<Vec<NodeInfo>> {
+        let args = QueryArgs {
+            target: Some(ByteBuf::from(target.0.to_vec())),
+            ..Default::default()
+        };
+        let response = self.query(addr, "find_node", args).await?;
+        Ok(self.learn_nodes(&response))
+    }
+
+    pub async fn get_peers(
+        &mut self,
+        addr: SocketAddr,
+        info_hash: InfoHash,
+    ) -> anyhow::Result<GetPeers> {
+        self.get_peers_many(&[addr], info_hash).await.remove(0)
+    }
+
+    async fn get_peers_many(
+        &mut self,
+        addrs: &[SocketAddr],
+        info_hash: InfoHash,
+    ) -> Vec<anyhow::Result<GetPeers>> {
+        let queries = addrs
+            .iter()
+            .map(|addr| {
+                let args = QueryArgs {
+                    info_hash: Some(ByteBuf::from(info_hash.as_bytes().to_vec())),
+                    ..Default::default()
+                };
+                (*addr, "get_peers", args)
+            })
+            .collect();
+
+        self.query_many(queries)
+            .await
+            .into_iter()
+            .map(|response| {
+                let response = response?;
+                Ok(GetPeers {
+                    peers: response.peers(),
+                    nodes: self.learn_nodes(&response),
+                    token: response.token.map(ByteBuf::into_vec),
+                })
+            })
+            .collect()
+    }
+
+    /// Tells the node at `addr` we are downloading `info_hash` on `port`, using the token from
+    /// its `get_peers` response.
+    pub async fn announce_peer(
+        &mut self,
+        addr: SocketAddr,
+        info_hash: InfoHash,
+        port: u16,
+        token: &[u8],
+    ) -> anyhow::Result<()> {
+        let args = QueryArgs {
+            info_hash: Some(ByteBuf::from(info_hash.as_bytes().to_vec())),
+            port: Some(port),
+            token: Some(ByteBuf::from(token)),
+            ..Default::default()
+        };
+        self.query(addr, "announce_peer", args).await?;
+        Ok(())
+    }
+
+    /// Iteratively queries the nodes closest to `info_hash` for peers, starting from the routing
+    /// table, and returns every peer found. Up to `ALPHA` nodes are queried at once.
+    #[instrument(skip(self))]
+    pub async fn lookup_peers(&mut self, info_hash: InfoHash) -> anyhow::Result<PeerAddresses> {
+        let target = NodeId(*info_hash.as_bytes());
+        // Kept apart from the routing table, whose bucket near the target holds only K nodes and
+        // would drop the closer ones each answer brings
+        let mut shortlist: BTreeMap<[u8; 20], NodeInfo> = self
+            .table
+            .closest(&target, K)
+            .into_iter()
+            .map(|node| (node.id.distance(&target), node))
+            .collect();
+        let mut queried = HashSet::new();
+        let mut peers = HashSet::new();
+
+        for _ in 0..MAX_LOOKUP_ROUNDS {
+            // Done once the K closest nodes known have all been queried
+            let candidates: Vec<NodeInfo> = shortlist
+                .values()
+                .take(K)
+                .filter(|node| !queried.contains(&node.id))
+                .take(ALPHA)
+                .copied()
+                .collect();
+            if candidates.is_empty() {
+                break;
+            }
+
+            queried.extend(candidates.iter().map(|node| node.id));
+            let addrs: Vec<SocketAddr> = candidates.iter().map(|node| node.addr).collect();
+            let results = self.get_peers_many(&addrs, info_hash).await;
+
+            for (node, result) in candidates.iter().zip(results) {
+                match result {
+                    Ok(response) => {
+                        peers.extend(response.peers.0);
+                        for found in response.nodes.into_iter().filter(|n| n.id != self.id) {
+                            shortlist.entry(found.id.distance(&target)).or_insert(found);
+                        }
+                    }
+                    Err(e) => {
+                        debug!("get_peers to {} failed: {:#}", node.addr, e);
+                        self.table.remove(&node.id);
+                        shortlist.remove(&node.id.distance(&target));
+                    }
+                }
+            }
+        }
+
+        info!("DHT lookup found {} peers", peers.len());
+        Ok(PeerAddresses(peers.into_iter().collect()))
+    }
+
+    /// Sends a query and waits for the response with the same transaction id. The responding
+    /// node is added to the routing table.
+    async fn query(
+        &mut self,
+        addr: SocketAddr,
+        method: &str,
+        args: QueryArgs,
+    ) -> anyhow::Result<ResponseValues> {
+        self.query_many(vec![(addr, method, args)]).await.remove(0)
+    }
+
+    /// Sends every query at once and collects the responses, matched by address and transaction
+    /// id, until all arrived or `QUERY_TIMEOUT` passed. Returns one result per query, in order.
+    async fn query_many(
+        &mut self,
+        queries: Vec<(SocketAddr, &str, QueryArgs)>,
+    ) -> Vec<anyhow::Result<ResponseValues>> {
+        let mut pending = Vec::with_capacity(queries.len());
+        let mut results: Vec<Option<anyhow::Result<ResponseValues>>> = Vec::new();
+
+        for (addr, method, mut args) in queries {
+            self.transaction_id = self.transaction_id.wrapping_add(1);
+            let transaction_id = self.transaction_id.to_be_bytes();
+            pending.push((addr, method, transaction_id));
+
+            args.id = ByteBuf::from(self.id.0.to_vec());
+            let sent = match KrpcMessage::query(&transaction_id, method, args).to_bytes() {
+                Ok(packet) => self
+                    .socket
+                    .send_to(&packet, addr)
+                    .await
+                    .context("Failed to send DHT query")
+                    .map(|_| ()),
+                Err(e) => Err(e),
+            };
+            results.push(sent.err().map(Err));
+        }
+
+        let deadline = Instant::now() + QUERY_TIMEOUT;
+        let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+        while results.iter().any(Option::is_none) {
+            let received = match timeout(
+                deadline.saturating_duration_since(Instant::now()),
+                self.socket.recv_from(&mut buffer),
+            )
+            .await
+            {
+                Ok(received) => received,
+                Err(_) => break,
+            };
+            let (length, from) = match received {
+                Ok(received) => received,
+                Err(e) => {
+                    debug!("Failed to receive DHT response: {}", e);
+                    continue;
+                }
+            };
+
+            // Late answers to earlier queries and packets from other nodes are dropped
+            let Ok(message) = KrpcMessage::from_bytes(&buffer[..length]) else {
+                continue;
+            };
+            let Some(index) =
+                pending
+                    .iter()
+                    .zip(&results)
+                    .position(|((addr, _, transaction_id), result)| {
+                        result.is_none() && *addr == from && message.t.as_slice() == transaction_id
+                    })
+            else {
+                continue;
+            };
+
+            let response = message.into_response().and_then(|response| {
+                self.table.insert(NodeInfo {
+                    id: response.node_id()?,
+                    addr: from,
+                });
+                Ok(response)
+            });
+            results[index] = Some(response);
+        }
+
+        results
+            .into_iter()
+            .zip(pending)
+            .map(|(result, (_, method, _))| {
+                result.unwrap_or_else(|| Err(anyhow::anyhow!("DHT query {} timed out", method)))
+            })
+            .collect()
+    }
+
+    fn learn_nodes(&mut self, response: &ResponseValues) -> Vec<NodeInfo> {
+        let nodes = response.nodes();
+        for node in &nodes {
+            self.table.insert(*node);
+        }
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fake node answering every query with `r`, echoing the transaction id.
+    async fn fake_node(r: ResponseValues) -> anyhow::Result<SocketAddr> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let addr = socket.local_addr()?;
+
+        tokio::spawn(async move {
+            let mut buffer = [0u8; MAX_PACKET_SIZE];
+            loop {
+                let (length, from) = socket.recv_from(&mut buffer).await.unwrap();
+                let query = KrpcMessage::from_bytes(&buffer[..length]).unwrap();
+                let response = KrpcMessage {
+                    t: query.t,
+                    y: "r".to_string(),
+                    q: None,
+                    a: None,
+                    r: Some(r.clone()),
+                    e: None,
+                };
+                socket
+                    .send_to(&response.to_bytes().unwrap(), from)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn test_ping_adds_node() -> anyhow::Result<()> {
+        let addr = fake_node(ResponseValues {
+            id: ByteBuf::from(vec![7u8; 20]),
+            ..Default::default()
+        })
+        .await?;
+
+        let mut node = DhtNode::bind("127.0.0.1:0").await?;
+        assert_eq!(node.ping(addr).await?, NodeId([7; 20]));
+        assert_eq!(node.routing_table().len(), 1);
+        Ok(())
+    }
+
+    fn compact_node(id: [u8; 20], addr: SocketAddr) -> ByteBuf {
+        let SocketAddr::V4(addr) = addr else {
+            panic!("Compact node info is IPv4 only");
+        };
+        let mut compact = id.to_vec();
+        compact.extend_from_slice(&addr.ip().octets());
+        compact.extend_from_slice(&addr.port().to_be_bytes());
+        ByteBuf::from(compact)
+    }
+
+    #[tokio::test]
+    async fn test_lookup_past_full_bucket() -> anyhow::Result<()> {
+        let closest = fake_node(ResponseValues {
+            id: ByteBuf::from(vec![0xFF; 20]),
+            values: Some(vec![ByteBuf::from(vec![10, 0, 0, 2, 0x1A, 0xE1])]),
+            ..Default::default()
+        })
+        .await?;
+        let closer = fake_node(ResponseValues {
+            id: ByteBuf::from(vec![0xFE; 20]),
+            nodes: Some(compact_node([0xFF; 20], closest)),
+            ..Default::default()
+        })
+        .await?;
+        let far = fake_node(ResponseValues {
+            id: ByteBuf::from(vec![0x80; 20]),
+            nodes: Some(compact_node([0xFE; 20], closer)),
+            ..Default::default()
+        })
+        .await?;
+
+        // With our id 0, every node above lands in the same bucket, which is full
+        let mut node = DhtNode::bind("127.0.0.1:0").await?;
+        node.id = NodeId([0; 20]);
+        node.table = RoutingTable::new(node.id);
+        for i in 0..K as u8 {
+            node.table.insert(NodeInfo {
+                id: NodeId([0x80 + i; 20]),
+                addr: far,
+            });
+        }
+
+        let peers = node.lookup_peers(InfoHash::new([0xFF; 20])).await?;
+        assert_eq!(peers.0, vec!["10.0.0.2:6881".parse().unwrap()]);
+        // The closer nodes were only reachable through the shortlist
+        assert!(node
+            .routing_table()
+            .closest(&NodeId([0xFF; 20]), K)
+            .iter()
+            .all(|known| known.addr == far));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lookup_queries_concurrently() -> anyhow::Result<()> {
+        let mut silent = Vec::new();
+        let mut node = DhtNode::bind("127.0.0.1:0").await?;
+        for i in 0..ALPHA as u8 {
+            let socket = UdpSocket::bind("127.0.0.1:0").await?;
+            node.table.insert(NodeInfo {
+                id: NodeId([i; 20]),
+                addr: socket.local_addr()?,
+            });
+            silent.push(socket);
+        }
+
+        let started = Instant::now();
+        let peers = node.lookup_peers(InfoHash::new([1; 20])).await?;
+        assert!(peers.is_empty());
+        // The nodes timed out together rather than one after another
+        assert!(started.elapsed() < QUERY_TIMEOUT * 2);
+        assert!(node.routing_table().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lookup_peers() -> anyhow::Result<()> {
+        let addr = fake_node(ResponseValues {
+            id: ByteBuf::from(vec![7u8; 20]),
+            values: Some(vec![ByteBuf::from(vec![10, 0, 0, 1, 0x1A, 0xE1])]),
+            token: Some(ByteBuf::from(&b"token"[..])),
+            ..Default::default()
+        })
+        .await?;
+
+        let mut node = DhtNode::bind("127.0.0.1:0").await?;
+        node.ping(addr).await?;
+
+        let peers = node.lookup_peers(InfoHash::new([1; 20])).await?;
+        assert_eq!(peers.0, vec!["10.0.0.1:6881".parse().unwrap()]);
+        Ok(())
+    }
+}
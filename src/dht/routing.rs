@@ -0,0 +1,196 @@
+use std::net::{Ipv4Addr, SocketAddr};
+
+/// Maximum number of nodes per bucket.
+pub const K: usize = 8;
+
+const ID_BITS: usize = 160;
+const COMPACT_NODE_LENGTH: usize = 26;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub [u8; 20]);
+
+impl NodeId {
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+
+    /// XOR distance to `other`, which compares like a 160-bit big-endian integer.
+    pub fn distance(&self, other: &NodeId) -> [u8; 20] {
+        let mut distance = [0u8; 20];
+        for (i, byte) in distance.iter_mut().enumerate() {
+            *byte = self.0[i] ^ other.0[i];
+        }
+        distance
+    }
+
+    /// Index of the bucket `other` belongs to, the position of the highest bit in which the ids
+    /// differ. `None` for our own id.
+    pub fn bucket_index(&self, other: &NodeId) -> Option<usize> {
+        let distance = self.distance(other);
+        let leading_zeros = distance
+            .iter()
+            .position(|byte| *byte != 0)
+            .map(|i| i * 8 + distance[i].leading_zeros() as usize)?;
+        Some(ID_BITS - 1 - leading_zeros)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeInfo {
+    pub id: NodeId,
+    pub addr: SocketAddr,
+}
+
+impl NodeInfo {
+    /// Parses compact node info, 20 bytes of id followed by 6 bytes of IPv4 address and port per
+    /// node. Returns `None` if the length is not a multiple of 26.
+    pub fn from_compact(v: &[u8]) -> Option<Vec<Self>> {
+        if !v.len().is_multiple_of(COMPACT_NODE_LENGTH) {
+            return None;
+        }
+        Some(
+            v.chunks_exact(COMPACT_NODE_LENGTH)
+                .map(|slice_26| NodeInfo {
+                    id: NodeId(slice_26[..20].try_into().unwrap()),
+                    addr: SocketAddr::new(
+                        Ipv4Addr::new(slice_26[20], slice_26[21], slice_26[22], slice_26[23])
+                            .into(),
+                        u16::from_be_bytes([slice_26[24], slice_26[25]]),
+                    ),
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Known nodes grouped in one bucket per bit of XOR distance from our id.
+#[derive(Debug)]
+pub struct RoutingTable {
+    own_id: NodeId,
+    buckets: Vec<Vec<NodeInfo>>,
+}
+
+impl RoutingTable {
+    pub fn new(own_id: NodeId) -> Self {
+        Self {
+            own_id,
+            buckets: vec![Vec::new(); ID_BITS],
+        }
+    }
+
+    /// Adds or refreshes `node`. Known nodes move to the back of their bucket as most recently
+    /// seen, new nodes are dropped when their bucket already holds `K` nodes. Returns whether the
+    /// node is in the table afterwards.
+    pub fn insert(&mut self, node: NodeInfo) -> bool {
+        let Some(index) = self.own_id.bucket_index(&node.id) else {
+            return false;
+        };
+        let bucket = &mut self.buckets[index];
+
+        if let Some(position) = bucket.iter().position(|known| known.id == node.id) {
+            bucket.remove(position);
+        } else if bucket.len() >= K {
+            return false;
+        }
+        bucket.push(node);
+        true
+    }
+
+    pub fn remove(&mut self, id: &NodeId) {
+        if let Some(index) = self.own_id.bucket_index(id) {
+            self.buckets[index].retain(|node| node.id != *id);
+        }
+    }
+
+    /// Up to `count` known nodes, closest to `target` first.
+    pub fn closest(&self, target: &NodeId, count: usize) -> Vec<NodeInfo> {
+        let mut nodes: Vec<NodeInfo> = self.buckets.iter().flatten().copied().collect();
+        nodes.sort_by_key(|node| node.id.distance(target));
+        nodes.truncate(count);
+        nodes
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(last_byte: u8) -> NodeId {
+        let mut id = [0u8; 20];
+        id[19] = last_byte;
+        NodeId(id)
+    }
+
+    fn node(id: NodeId) -> NodeInfo {
+        NodeInfo {
+            id,
+            addr: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 6881),
+        }
+    }
+
+    #[test]
+    fn test_distance() {
+        assert_eq!(id(0b1010).distance(&id(0b0110))[19], 0b1100);
+        assert_eq!(id(7).distance(&id(7)), [0; 20]);
+    }
+
+    #[test]
+    fn test_bucket_index() {
+        let own = id(0);
+        assert_eq!(own.bucket_index(&own), None);
+        assert_eq!(own.bucket_index(&id(1)), Some(0));
+        assert_eq!(own.bucket_index(&id(0b1000_0000)), Some(7));
+
+        let mut far = [0u8; 20];
+        far[0] = 0x80;
+        assert_eq!(own.bucket_index(&NodeId(far)), Some(159));
+    }
+
+    #[test]
+    fn test_bucket_holds_at_most_k_nodes() {
+        let mut table = RoutingTable::new(id(0));
+        // Ids 128..=255 all share bucket 7
+        for last_byte in 128..128 + K as u8 {
+            assert!(table.insert(node(id(last_byte))));
+        }
+        assert!(!table.insert(node(id(200))));
+        // Refreshing a known node still succeeds
+        assert!(table.insert(node(id(128))));
+        assert_eq!(table.len(), K);
+    }
+
+    #[test]
+    fn test_closest() {
+        let mut table = RoutingTable::new(id(0));
+        for last_byte in [1, 2, 4, 8, 16] {
+            table.insert(node(id(last_byte)));
+        }
+
+        let closest: Vec<NodeId> = table
+            .closest(&id(5), 3)
+            .into_iter()
+            .map(|node| node.id)
+            .collect();
+        assert_eq!(closest, vec![id(4), id(1), id(2)]);
+    }
+
+    #[test]
+    fn test_compact_node_info() {
+        let mut compact = vec![3u8; 20];
+        compact.extend_from_slice(&[10, 0, 0, 1, 0x1A, 0xE1]);
+
+        let nodes = NodeInfo::from_compact(&compact).unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].id, NodeId([3; 20]));
+        assert_eq!(nodes[0].addr, "10.0.0.1:6881".parse().unwrap());
+        assert!(NodeInfo::from_compact(&compact[..25]).is_none());
+    }
+}
@@ -1,4 +1,6 @@
+pub mod dht;
 pub mod message;
 pub mod peer;
+pub mod piece;
 pub mod torrent;
 pub mod tracker;
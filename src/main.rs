@@ -1,11 +1,143 @@
-use torrent_rs::torrent;
-use tracing::info;
-use tracing_subscriber;
+use std::path::PathBuf;
 
-fn main() {
-    tracing_subscriber::fmt::init();
+use anyhow::bail;
+use clap::{Parser, Subcommand};
+use torrent_rs::{
+    message::Bitfield,
+    piece::verify_all,
+    torrent::Torrent,
+    tracker::{HttpConfig, TrackerRequest},
+};
 
-    let torrent =
-        torrent::open("example/debian-12.7.0-amd64-netinst.iso.torrent".to_string()).unwrap();
-    info!("{:?}", torrent)
+// Trackers may return no peers on the first announce
+const ANNOUNCE_ATTEMPTS: usize = 3;
+
+#[derive(Parser)]
+#[command(version, about = "A BitTorrent client")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Download the torrent's content
+    Download {
+        file: PathBuf,
+
+        /// Directory to save the content in
+        #[arg(long, default_value = ".")]
+        output: PathBuf,
+
+        /// Maximum number of connected peers
+        #[arg(long, default_value_t = 50)]
+        max_peers: usize,
+    },
+    /// Print the torrent's metadata
+    Info {
+        file: PathBuf,
+
+        /// Print the metadata as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Announce to the trackers and list the peers they return
+    Peers { file: PathBuf },
+    /// Check downloaded content against the torrent's piece hashes
+    Verify {
+        file: PathBuf,
+
+        /// Directory the content was saved in
+        #[arg(long, default_value = ".")]
+        data: PathBuf,
+    },
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
+    match Cli::parse().command {
+        Command::Download { .. } => {
+            bail!("Downloading is not supported yet, use `info` or `peers`")
+        }
+        Command::Info { file, json } => {
+            let torrent = Torrent::open(file).await?;
+            if json {
+                println!("{:#}", torrent.to_json());
+            } else {
+                print_info(&torrent)?;
+            }
+        }
+        Command::Peers { file } => {
+            let torrent = Torrent::open(file).await?;
+            let client = HttpConfig::default().build_client()?;
+            let response = TrackerRequest::announce_for_peers(
+                &client,
+                &torrent,
+                TrackerRequest::generate_peer_id(),
+                ANNOUNCE_ATTEMPTS,
+            )
+            .await?;
+            for peer in response.peers() {
+                println!("{}", peer);
+            }
+        }
+        Command::Verify { file, data } => {
+            let torrent = Torrent::open(file).await?;
+            let report = verify_all(&torrent, &data).await?;
+            println!("Good: {}", report.good.len());
+            println!("Bad: {} {:?}", report.bad.len(), report.bad);
+            println!("Missing: {} {:?}", report.missing.len(), report.missing);
+            if torrent.total_files() > 1 {
+                let mut completed = Bitfield::new(torrent.info.pieces.0.len());
+                report
+                    .good
+                    .iter()
+                    .for_each(|piece| completed.set_piece(*piece));
+                for file in torrent.file_progress(&completed) {
+                    println!(
+                        "  {}: {}/{} bytes",
+                        file.path.display(),
+                        file.completed_bytes,
+                        file.total_bytes
+                    );
+                }
+            }
+            if !report.is_complete() {
+                bail!("Content does not match the torrent");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_info(torrent: &Torrent) -> std::io::Result<()> {
+    println!("Name: {}", torrent.info.name);
+    println!("Size: {} bytes", torrent.length());
+    println!("Piece length: {} bytes", torrent.info.piece_length);
+    println!("Pieces: {}", torrent.info.pieces.0.len());
+    if let Some(comment) = torrent.comment() {
+        println!("Comment: {}", comment);
+    }
+    if let Some(created_by) = torrent.created_by() {
+        println!("Created by: {}", created_by);
+    }
+    if let Some(creation_date) = torrent.creation_date {
+        println!("Creation date: {}", creation_date);
+    }
+    if let Some(info_hash) = torrent.info_hash {
+        println!("Info hash: {}", info_hash);
+    }
+    println!("Trackers:");
+    for (tier, urls) in torrent.trackers().iter().enumerate() {
+        for url in urls {
+            println!("  [{}] {}", tier, url);
+        }
+    }
+    println!("Files:");
+    torrent.print_tree(&mut std::io::stdout())
 }
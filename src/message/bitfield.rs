@@ -4,10 +4,28 @@ pub struct Bitfield {
 }
 
 impl Bitfield {
+    /// An empty bitfield large enough to hold `total_pieces` pieces.
+    pub fn new(total_pieces: usize) -> Self {
+        Self {
+            data: vec![0; total_pieces.div_ceil(8)],
+        }
+    }
+
+    /// A bitfield with all `total_pieces` pieces set and the spare bits of the last byte clear.
+    pub fn full(total_pieces: usize) -> Self {
+        let mut bitfield = Self::new(total_pieces);
+        (0..total_pieces).for_each(|index| bitfield.set_piece(index));
+        bitfield
+    }
+
     pub fn from_bytes(bytes: Vec<u8>) -> Self {
         Self { data: bytes }
     }
 
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn has_piece(&self, index: usize) -> bool {
         let byte_index = index / 8;
         let bit_index = index % 8;
@@ -20,4 +38,144 @@ impl Bitfield {
         // Big endian bit ordering
         self.data[byte_index] & (1 << (7 - bit_index)) != 0
     }
+
+    /// Marks the piece as available, out of range indices are ignored.
+    pub fn set_piece(&mut self, index: usize) {
+        if let Some(byte) = self.data.get_mut(index / 8) {
+            *byte |= 1 << (7 - index % 8);
+        }
+    }
+
+    /// Marks the piece as missing, out of range indices are ignored.
+    pub fn clear_piece(&mut self, index: usize) {
+        if let Some(byte) = self.data.get_mut(index / 8) {
+            *byte &= !(1 << (7 - index % 8));
+        }
+    }
+
+    /// Number of pieces marked as available.
+    pub fn count_set(&self) -> usize {
+        self.data
+            .iter()
+            .map(|byte| byte.count_ones() as usize)
+            .sum()
+    }
+
+    /// Number of bits in the bitfield, including any spare bits in the last byte.
+    pub fn len(&self) -> usize {
+        self.data.len() * 8
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Iterates over the indices of every piece that is set.
+    pub fn pieces(&self) -> BitfieldIterator<'_> {
+        BitfieldIterator {
+            bitfield: self,
+            index: 0,
+        }
+    }
+}
+
+pub struct BitfieldIterator<'a> {
+    bitfield: &'a Bitfield,
+    index: usize,
+}
+
+impl Iterator for BitfieldIterator<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Check the current index before advancing so piece 0 is never skipped
+        while self.index < self.bitfield.len() {
+            let index = self.index;
+            self.index += 1;
+            if self.bitfield.has_piece(index) {
+                return Some(index);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_clears_spare_bits() {
+        let bitfield = Bitfield::full(10);
+        assert_eq!(bitfield.as_bytes(), &[0xFF, 0b1100_0000]);
+        assert_eq!(bitfield.count_set(), 10);
+    }
+
+    #[test]
+    fn test_iterator_yields_first_piece() {
+        let bitfield = Bitfield::from_bytes(vec![0b10000000]);
+        assert_eq!(bitfield.pieces().collect::<Vec<_>>(), vec![0]);
+    }
+
+    #[test]
+    fn test_iterator_yields_last_bit_of_byte() {
+        let bitfield = Bitfield::from_bytes(vec![0b00000001]);
+        assert_eq!(bitfield.pieces().collect::<Vec<_>>(), vec![7]);
+    }
+
+    #[test]
+    fn test_iterator_spans_bytes() {
+        let bitfield = Bitfield::from_bytes(vec![0b10000001, 0b01000000]);
+        assert_eq!(bitfield.pieces().collect::<Vec<_>>(), vec![0, 7, 9]);
+        assert!(bitfield.pieces().all(|index| index < bitfield.len()));
+    }
+
+    #[test]
+    fn test_new_rounds_up_to_whole_bytes() {
+        assert_eq!(Bitfield::new(0).as_bytes(), &[] as &[u8]);
+        assert_eq!(Bitfield::new(8).as_bytes(), &[0]);
+        assert_eq!(Bitfield::new(9).as_bytes(), &[0, 0]);
+    }
+
+    #[test]
+    fn test_set_and_has_piece() {
+        let mut bitfield = Bitfield::new(19);
+        for index in [0, 7, 8, 15, 16, 18] {
+            assert!(!bitfield.has_piece(index));
+            bitfield.set_piece(index);
+            assert!(bitfield.has_piece(index));
+        }
+
+        assert_eq!(bitfield.as_bytes(), &[0b10000001, 0b10000001, 0b10100000]);
+        assert_eq!(bitfield.count_set(), 6);
+        assert_eq!(
+            bitfield.pieces().collect::<Vec<_>>(),
+            vec![0, 7, 8, 15, 16, 18]
+        );
+    }
+
+    #[test]
+    fn test_clear_piece() {
+        let mut bitfield = Bitfield::from_bytes(vec![0xFF, 0xFF]);
+        bitfield.clear_piece(0);
+        bitfield.clear_piece(9);
+        assert!(!bitfield.has_piece(0));
+        assert!(!bitfield.has_piece(9));
+        assert!(bitfield.has_piece(1));
+        assert_eq!(bitfield.count_set(), 14);
+    }
+
+    #[test]
+    fn test_set_out_of_range_is_ignored() {
+        let mut bitfield = Bitfield::new(8);
+        bitfield.set_piece(8);
+        bitfield.clear_piece(100);
+        assert_eq!(bitfield.count_set(), 0);
+    }
+
+    #[test]
+    fn test_iterator_empty() {
+        let bitfield = Bitfield::from_bytes(vec![]);
+        assert_eq!(bitfield.pieces().next(), None);
+    }
 }
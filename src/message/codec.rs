@@ -6,7 +6,7 @@ use tokio_util::codec::{Decoder, Encoder};
 use super::PeerMessage;
 
 // DDoS Protection
-const MAX_MESSAGE_SIZE: usize = 16 * 1024; // 16 MB
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024; // 16 MB
 #[derive(Debug)]
 pub struct MessageCodec;
 
@@ -21,13 +21,13 @@ impl Decoder for MessageCodec {
             return Ok(None);
         }
 
-        let length = src.get_u32() as usize;
-        if length == 0 {
-            return Ok(Some(PeerMessage::KeepAlive));
-        }
+        // Peek the length so a partial frame is left intact in the buffer
+        let mut length_bytes = [0u8; 4];
+        length_bytes.copy_from_slice(&src[..4]);
+        let length = u32::from_be_bytes(length_bytes) as usize;
 
         // DDoS Protection
-        if length > MAX_MESSAGE_SIZE || src.len() > MAX_MESSAGE_SIZE {
+        if length > MAX_MESSAGE_SIZE {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "Message length exceeds maximum allowed size",
@@ -35,13 +35,32 @@ impl Decoder for MessageCodec {
         }
 
         // Not full frame is  received, wait for more
-        if src.len() < length {
+        if src.len() < 4 + length {
+            src.reserve(4 + length - src.len());
             return Ok(None);
         }
 
+        src.advance(4);
+        if length == 0 {
+            return Ok(Some(PeerMessage::KeepAlive));
+        }
+
         // ID is a single decimal byte
         let id = src.get_u8();
 
+        // Fixed size messages must match exactly, a mismatch would read past the frame or leave
+        // bytes behind that desync the stream
+        let expected_length = match id {
+            0..=3 | 0x0E | 0x0F => Some(1),
+            4 | 0x0D | 0x11 => Some(5),
+            6 | 8 | 0x10 => Some(13),
+            9 => Some(3),
+            _ => None,
+        };
+        if expected_length.is_some_and(|expected| expected != length) || (id == 7 && length < 9) {
+            return Err(invalid_length(id, length));
+        }
+
         let message = match id {
             0 => PeerMessage::Choke,
             1 => PeerMessage::Unchoke,
@@ -90,6 +109,29 @@ impl Decoder for MessageCodec {
                 let port = src.get_u16();
                 PeerMessage::Port(port)
             }
+            0x0D => PeerMessage::SuggestPiece(src.get_u32()),
+            0x0E => PeerMessage::HaveAll,
+            0x0F => PeerMessage::HaveNone,
+            0x10 => {
+                let index = src.get_u32();
+                let begin = src.get_u32();
+                let length = src.get_u32();
+                PeerMessage::RejectRequest {
+                    index,
+                    begin,
+                    length,
+                }
+            }
+            0x11 => PeerMessage::AllowedFast(src.get_u32()),
+            20 => {
+                if length < 2 {
+                    return Err(invalid_length(id, length));
+                }
+                let ext_id = src.get_u8();
+                // IDs and extended message ID are 2 bytes
+                let payload = src.split_to(length - 2).to_vec();
+                PeerMessage::Extended { ext_id, payload }
+            }
 
             _ => {
                 return Err(io::Error::new(
@@ -107,6 +149,9 @@ impl Encoder<PeerMessage> for MessageCodec {
     type Error = std::io::Error;
 
     fn encode(&mut self, item: PeerMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        // Length prefix plus ID and payload, reserved up front so large blocks don't reallocate
+        dst.reserve(4 + encoded_length(&item));
+
         match item {
             PeerMessage::KeepAlive => {
                 dst.put_u32(0); // Length prefix is 0 for KeepAlive
@@ -171,20 +216,91 @@ impl Encoder<PeerMessage> for MessageCodec {
                 dst.put_u32(length);
             }
             PeerMessage::Port(port) => {
-                dst.put_u32(6); // Length
+                dst.put_u32(3); // Length prefix
                 dst.put_u8(9); // Message ID
                 dst.put_u16(port);
             }
+            PeerMessage::SuggestPiece(index) => {
+                dst.put_u32(5); // Length prefix
+                dst.put_u8(0x0D); // Message ID
+                dst.put_u32(index);
+            }
+            PeerMessage::HaveAll => {
+                dst.put_u32(1);
+                dst.put_u8(0x0E);
+            }
+            PeerMessage::HaveNone => {
+                dst.put_u32(1);
+                dst.put_u8(0x0F);
+            }
+            PeerMessage::RejectRequest {
+                index,
+                begin,
+                length,
+            } => {
+                dst.put_u32(13); // Length prefix
+                dst.put_u8(0x10); // Message ID
+                dst.put_u32(index);
+                dst.put_u32(begin);
+                dst.put_u32(length);
+            }
+            PeerMessage::AllowedFast(index) => {
+                dst.put_u32(5); // Length prefix
+                dst.put_u8(0x11); // Message ID
+                dst.put_u32(index);
+            }
+            PeerMessage::Extended { ext_id, payload } => {
+                dst.put_u32(2 + payload.len() as u32); // Length prefix
+                dst.put_u8(20); // Message ID
+                dst.put_u8(ext_id);
+                dst.extend_from_slice(&payload);
+            }
         }
         Ok(())
     }
 }
 
+fn invalid_length(id: u8, length: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid length {} for message ID {}", length, id),
+    )
+}
+
+/// Number of bytes following the length prefix, i.e. the message ID plus its payload.
+fn encoded_length(message: &PeerMessage) -> usize {
+    match message {
+        PeerMessage::KeepAlive => 0,
+        PeerMessage::Choke
+        | PeerMessage::Unchoke
+        | PeerMessage::Interested
+        | PeerMessage::NotInterested
+        | PeerMessage::HaveAll
+        | PeerMessage::HaveNone => 1,
+        PeerMessage::Have(_) | PeerMessage::SuggestPiece(_) | PeerMessage::AllowedFast(_) => 5,
+        PeerMessage::Bitfield(bitfield) => 1 + bitfield.len(),
+        PeerMessage::Request { .. }
+        | PeerMessage::Cancel { .. }
+        | PeerMessage::RejectRequest { .. } => 13,
+        PeerMessage::Piece { block, .. } => 9 + block.len(),
+        PeerMessage::Port(_) => 3,
+        PeerMessage::Extended { payload, .. } => 2 + payload.len(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::message::BLOCK_SIZE;
     use tokio_util::bytes::BytesMut;
-    use tokio_util::codec::Decoder;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    fn round_trip(message: PeerMessage) -> Option<PeerMessage> {
+        let mut codec = MessageCodec;
+        let mut buffer = BytesMut::new();
+        codec.encode(message, &mut buffer).unwrap();
+        codec.decode(&mut buffer).unwrap()
+    }
 
     #[test]
     fn test_decode_keep_alive() {
@@ -252,4 +368,216 @@ mod tests {
             Some(PeerMessage::Bitfield(vec![0b10101010, 0b11110000]))
         );
     }
+
+    #[test]
+    fn test_encode_keep_alive() {
+        let mut codec = MessageCodec;
+        let mut buffer = BytesMut::new();
+        codec.encode(PeerMessage::KeepAlive, &mut buffer).unwrap();
+        assert_eq!(&buffer[..], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_encode_request() {
+        let mut codec = MessageCodec;
+        let mut buffer = BytesMut::new();
+        let request = PeerMessage::Request {
+            index: 1,
+            begin: 16384,
+            length: 16384,
+        };
+        codec.encode(request, &mut buffer).unwrap();
+        assert_eq!(
+            &buffer[..],
+            &[0, 0, 0, 13, 6, 0, 0, 0, 1, 0, 0, 64, 0, 0, 0, 64, 0]
+        );
+    }
+
+    #[test]
+    fn test_round_trip_all_variants() {
+        let messages = vec![
+            PeerMessage::KeepAlive,
+            PeerMessage::Choke,
+            PeerMessage::Unchoke,
+            PeerMessage::Interested,
+            PeerMessage::NotInterested,
+            PeerMessage::Have(42),
+            PeerMessage::Bitfield(vec![0b10101010, 0b11110000]),
+            PeerMessage::Request {
+                index: 1,
+                begin: 2,
+                length: 3,
+            },
+            PeerMessage::Piece {
+                index: 4,
+                begin: 5,
+                block: vec![1, 2, 3, 4, 5, 6],
+            },
+            PeerMessage::Cancel {
+                index: 7,
+                begin: 8,
+                length: 9,
+            },
+            PeerMessage::Port(6881),
+            PeerMessage::Extended {
+                ext_id: 0,
+                payload: b"d1:md11:ut_metadatai1eee".to_vec(),
+            },
+            PeerMessage::Extended {
+                ext_id: 3,
+                payload: vec![],
+            },
+        ];
+
+        for message in messages {
+            assert_eq!(round_trip(message.clone()), Some(message));
+        }
+    }
+
+    #[test]
+    fn test_fast_extension_round_trip() {
+        let messages = vec![
+            (PeerMessage::SuggestPiece(7), 0x0D),
+            (PeerMessage::HaveAll, 0x0E),
+            (PeerMessage::HaveNone, 0x0F),
+            (
+                PeerMessage::RejectRequest {
+                    index: 1,
+                    begin: BLOCK_SIZE,
+                    length: BLOCK_SIZE,
+                },
+                0x10,
+            ),
+            (PeerMessage::AllowedFast(42), 0x11),
+        ];
+
+        for (message, id) in messages {
+            let mut buffer = BytesMut::new();
+            MessageCodec.encode(message.clone(), &mut buffer).unwrap();
+            assert_eq!(buffer[4], id);
+            assert_eq!(message.message_id(), Some(id));
+            assert_eq!(round_trip(message.clone()), Some(message));
+        }
+    }
+
+    #[test]
+    fn test_decode_extended() {
+        let mut codec = MessageCodec;
+        let mut buffer = BytesMut::from(&[0, 0, 0, 4, 20, 1, b'd', b'e'][..]);
+        let message = codec.decode(&mut buffer).unwrap();
+        assert_eq!(
+            message,
+            Some(PeerMessage::Extended {
+                ext_id: 1,
+                payload: b"de".to_vec(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_fast_extension_wrong_length() {
+        let frames: [&[u8]; 4] = [
+            // SuggestPiece missing its index, followed by another frame
+            &[0, 0, 0, 1, 0x0D, 0, 0, 0, 1, 1],
+            &[0, 0, 0, 2, 0x0E, 0],
+            &[0, 0, 0, 3, 0x0F, 0, 0],
+            &[0, 0, 0, 9, 0x10, 0, 0, 0, 1, 0, 0, 0, 2],
+        ];
+
+        for frame in frames {
+            let mut buffer = BytesMut::from(frame);
+            let error = MessageCodec.decode(&mut buffer).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{:?}", frame);
+        }
+    }
+
+    #[test]
+    fn test_core_message_wrong_length() {
+        let frames: [&[u8]; 12] = [
+            // Choke, Unchoke, Interested and NotInterested with a trailing byte
+            &[0, 0, 0, 2, 0, 0],
+            &[0, 0, 0, 2, 1, 0],
+            &[0, 0, 0, 2, 2, 0],
+            &[0, 0, 0, 2, 3, 0],
+            // Have without its index, and with one byte too many
+            &[0, 0, 0, 1, 4],
+            &[0, 0, 0, 6, 4, 0, 0, 0, 1, 0],
+            // Request and Cancel one byte short
+            &[0, 0, 0, 12, 6, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0],
+            &[0, 0, 0, 12, 8, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0],
+            // Piece without index and begin, and with a truncated begin
+            &[0, 0, 0, 1, 7],
+            &[0, 0, 0, 8, 7, 0, 0, 0, 1, 0, 0, 0],
+            // Port without its port, and with one byte too many
+            &[0, 0, 0, 1, 9],
+            &[0, 0, 0, 4, 9, 0x1A, 0xE1, 0],
+        ];
+
+        for frame in frames {
+            let mut buffer = BytesMut::from(frame);
+            let error = MessageCodec.decode(&mut buffer).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{:?}", frame);
+        }
+    }
+
+    #[test]
+    fn test_decode_extended_without_ext_id() {
+        let mut codec = MessageCodec;
+        let mut buffer = BytesMut::from(&[0, 0, 0, 1, 20][..]);
+        let error = codec.decode(&mut buffer).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_encode_reserves_capacity() {
+        let mut codec = MessageCodec;
+        let mut buffer = BytesMut::new();
+        let block = vec![0u8; 1024];
+        codec
+            .encode(
+                PeerMessage::Piece {
+                    index: 0,
+                    begin: 0,
+                    block,
+                },
+                &mut buffer,
+            )
+            .unwrap();
+        assert_eq!(buffer.len(), 4 + 9 + 1024);
+        assert!(buffer.capacity() >= 4 + 9 + 1024);
+    }
+
+    #[test]
+    fn test_decode_full_block_piece() {
+        let mut codec = MessageCodec;
+        let message = PeerMessage::Piece {
+            index: 3,
+            begin: BLOCK_SIZE,
+            block: vec![0xAB; BLOCK_SIZE as usize],
+        };
+        let mut buffer = BytesMut::new();
+        codec.encode(message.clone(), &mut buffer).unwrap();
+
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(message));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_decode_piece_split_across_reads() {
+        let mut codec = MessageCodec;
+        let message = PeerMessage::Piece {
+            index: 0,
+            begin: 0,
+            block: vec![7; BLOCK_SIZE as usize],
+        };
+        let mut encoded = BytesMut::new();
+        codec.encode(message.clone(), &mut encoded).unwrap();
+
+        // First read only delivers part of the frame
+        let mut buffer = encoded.split_to(1000);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+
+        buffer.extend_from_slice(&encoded);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(message));
+    }
 }
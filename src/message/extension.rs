@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+
+use anyhow::Context;
+use serde_derive::{Deserialize, Serialize};
+
+/// Extended message id reserved for the extension protocol handshake (BEP 10).
+pub const EXTENDED_HANDSHAKE_ID: u8 = 0;
+
+/// Extended message id peers should use when sending us PEX messages.
+pub const UT_PEX_ID: u8 = 2;
+
+// ut_metadata is only advertised by Peer::fetch_metadata, as we can't serve metadata
+const SUPPORTED_EXTENSIONS: [(&str, u8); 1] = [("ut_pex", UT_PEX_ID)];
+
+/// Payload of the extended handshake, a bencoded dictionary.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ExtendedHandshake {
+    /// Maps extension names to the extended message id the sender wants to receive them with.
+    /// An id of 0 means the extension is disabled.
+    #[serde(default)]
+    pub m: BTreeMap<String, i64>,
+
+    /// Local TCP listen port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p: Option<u16>,
+
+    /// Client name and version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub v: Option<String>,
+
+    /// Size of the info dictionary in bytes, advertised by peers supporting ut_metadata.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata_size: Option<usize>,
+}
+
+impl ExtendedHandshake {
+    /// Our own handshake, advertising every extension we support. PEX is left out for private
+    /// torrents (BEP 27).
+    pub fn new(listen_port: Option<u16>, private: bool) -> Self {
+        Self {
+            m: SUPPORTED_EXTENSIONS
+                .iter()
+                .filter(|(name, _)| !(private && *name == "ut_pex"))
+                .map(|(name, id)| (name.to_string(), *id as i64))
+                .collect(),
+            p: listen_port,
+            v: Some(format!(
+                "{} {}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            )),
+            metadata_size: None,
+        }
+    }
+
+    pub fn from_bytes(payload: &[u8]) -> anyhow::Result<Self> {
+        serde_bencode::from_bytes(payload).context("Failed to parse extended handshake")
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        serde_bencode::to_bytes(self).context("Failed to encode extended handshake")
+    }
+
+    /// The id to send `extension` messages with, `None` if the sender doesn't support it.
+    pub fn extension_id(&self, extension: &str) -> Option<u8> {
+        self.m
+            .get(extension)
+            .and_then(|id| u8::try_from(*id).ok())
+            .filter(|id| *id != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_extended_handshake() {
+        let payload = b"d1:md11:ut_metadatai3e6:ut_pexi0ee13:metadata_sizei31235e1:pi6881e1:v14:uTorrent 3.5.5e";
+        let handshake = ExtendedHandshake::from_bytes(payload).unwrap();
+
+        assert_eq!(handshake.extension_id("ut_metadata"), Some(3));
+        // Disabled extensions and unknown ones both report no id
+        assert_eq!(handshake.extension_id("ut_pex"), None);
+        assert_eq!(handshake.extension_id("lt_donthave"), None);
+        assert_eq!(handshake.metadata_size, Some(31235));
+        assert_eq!(handshake.p, Some(6881));
+        assert_eq!(handshake.v.as_deref(), Some("uTorrent 3.5.5"));
+    }
+
+    #[test]
+    fn test_extended_handshake_round_trip() {
+        let handshake = ExtendedHandshake::new(Some(6889), false);
+        let bytes = handshake.to_bytes().unwrap();
+
+        assert!(bytes.starts_with(b"d1:md6:ut_pexi2ee1:pi6889e"));
+        assert_eq!(handshake.extension_id("ut_metadata"), None);
+        assert_eq!(ExtendedHandshake::from_bytes(&bytes).unwrap(), handshake);
+    }
+
+    #[test]
+    fn test_private_handshake_omits_pex() {
+        let handshake = ExtendedHandshake::new(None, true);
+        assert_eq!(handshake.extension_id("ut_pex"), None);
+        assert!(handshake.m.is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::{bail, Context};
+use serde_derive::{Deserialize, Serialize};
+
+/// Extended message id peers should use when sending us ut_metadata messages. Only advertised
+/// while fetching metadata, as we can't serve it.
+pub const UT_METADATA_ID: u8 = 1;
+
+/// Metadata is exchanged in pieces of 16 KiB, the last one possibly shorter (BEP 9).
+pub const METADATA_PIECE_SIZE: usize = 16 * 1024;
+
+// Headers are flat dictionaries, anything nested deeper is malformed
+const MAX_HEADER_DEPTH: usize = 4;
+
+/// Message of the ut_metadata extension (BEP 9), used to fetch the info dictionary of a magnet
+/// link from peers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataMessage {
+    Request {
+        piece: usize,
+    },
+    Data {
+        piece: usize,
+        total_size: usize,
+        data: Vec<u8>,
+    },
+    Reject {
+        piece: usize,
+    },
+}
+
+/// The bencoded dictionary every message starts with. Data messages append the piece's raw
+/// bytes after it.
+#[derive(Debug, Deserialize, Serialize)]
+struct Header {
+    msg_type: u8,
+    piece: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    total_size: Option<usize>,
+}
+
+impl MetadataMessage {
+    pub fn from_bytes(payload: &[u8]) -> anyhow::Result<Self> {
+        let end = bencode_end(payload, 0, 0).context("Invalid ut_metadata message header")?;
+        let header: Header = serde_bencode::from_bytes(&payload[..end])
+            .context("Failed to parse ut_metadata message header")?;
+
+        Ok(match header.msg_type {
+            0 => Self::Request {
+                piece: header.piece,
+            },
+            1 => Self::Data {
+                piece: header.piece,
+                total_size: header
+                    .total_size
+                    .context("ut_metadata data message has no total_size")?,
+                data: payload[end..].to_vec(),
+            },
+            2 => Self::Reject {
+                piece: header.piece,
+            },
+            msg_type => bail!("Unknown ut_metadata message type {}", msg_type),
+        })
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let (header, data) = match self {
+            Self::Request { piece } => (
+                Header {
+                    msg_type: 0,
+                    piece: *piece,
+                    total_size: None,
+                },
+                &[][..],
+            ),
+            Self::Data {
+                piece,
+                total_size,
+                data,
+            } => (
+                Header {
+                    msg_type: 1,
+                    piece: *piece,
+                    total_size: Some(*total_size),
+                },
+                &data[..],
+            ),
+            Self::Reject { piece } => (
+                Header {
+                    msg_type: 2,
+                    piece: *piece,
+                    total_size: None,
+                },
+                &[][..],
+            ),
+        };
+
+        let mut bytes =
+            serde_bencode::to_bytes(&header).context("Failed to encode ut_metadata message")?;
+        bytes.extend_from_slice(data);
+        Ok(bytes)
+    }
+}
+
+/// Offset just past the bencoded value starting at `start`, `None` if it is malformed or
+/// truncated.
+fn bencode_end(bytes: &[u8], start: usize, depth: usize) -> Option<usize> {
+    match *bytes.get(start)? {
+        b'i' => Some(start + bytes[start..].iter().position(|b| *b == b'e')? + 1),
+        b'l' | b'd' if depth < MAX_HEADER_DEPTH => {
+            let mut position = start + 1;
+            while *bytes.get(position)? != b'e' {
+                position = bencode_end(bytes, position, depth + 1)?;
+            }
+            Some(position + 1)
+        }
+        b'0'..=b'9' => {
+            let colon = start + bytes[start..].iter().position(|b| *b == b':')?;
+            let length: usize = std::str::from_utf8(&bytes[start..colon])
+                .ok()?
+                .parse()
+                .ok()?;
+            let end = colon.checked_add(1)?.checked_add(length)?;
+            (end <= bytes.len()).then_some(end)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_data_message() {
+        let payload = b"d8:msg_typei1e5:piecei0e10:total_sizei34256eeXXXX";
+        let message = MetadataMessage::from_bytes(payload).unwrap();
+        assert_eq!(
+            message,
+            MetadataMessage::Data {
+                piece: 0,
+                total_size: 34256,
+                data: b"XXXX".to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let messages = [
+            MetadataMessage::Request { piece: 2 },
+            MetadataMessage::Data {
+                piece: 1,
+                total_size: 20,
+                data: b"d4:name4:teste".to_vec(),
+            },
+            MetadataMessage::Reject { piece: 0 },
+        ];
+
+        for message in messages {
+            let bytes = message.to_bytes().unwrap();
+            assert_eq!(MetadataMessage::from_bytes(&bytes).unwrap(), message);
+        }
+        assert_eq!(
+            MetadataMessage::Request { piece: 0 }.to_bytes().unwrap(),
+            b"d8:msg_typei0e5:piecei0ee"
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_message() {
+        assert!(MetadataMessage::from_bytes(b"d8:msg_typei1e5:piecei0ee").is_err());
+        assert!(MetadataMessage::from_bytes(b"d8:msg_typei7e5:piecei0ee").is_err());
+        assert!(MetadataMessage::from_bytes(b"d8:msg_typei0e5:piecei0e").is_err());
+        assert!(MetadataMessage::from_bytes(b"d99:msg_type").is_err());
+        assert!(MetadataMessage::from_bytes(b"dldldldldldleeeeeeeeeeee").is_err());
+    }
+}
@@ -2,10 +2,19 @@
 
 mod bitfield;
 mod codec;
-pub use bitfield::Bitfield;
+mod extension;
+mod metadata;
+mod pex;
+pub use bitfield::{Bitfield, BitfieldIterator};
 pub use codec::MessageCodec;
+pub use extension::{ExtendedHandshake, EXTENDED_HANDSHAKE_ID, UT_PEX_ID};
+pub use metadata::{MetadataMessage, METADATA_PIECE_SIZE, UT_METADATA_ID};
+pub use pex::PexMessage;
 
-#[derive(Debug, PartialEq)]
+/// Size of a block requested from peers, 16 KiB as used by virtually all clients.
+pub const BLOCK_SIZE: u32 = 16 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum PeerMessage {
     KeepAlive,
     Choke,
@@ -30,6 +39,20 @@ pub enum PeerMessage {
         length: u32,
     },
     Port(u16), // For newer versions that implements DHT, stored in 2 bytes
+    // Fast extension (BEP 6), only valid when both sides set the reserved bit
+    SuggestPiece(u32),
+    HaveAll,
+    HaveNone,
+    RejectRequest {
+        index: u32,
+        begin: u32,
+        length: u32,
+    },
+    AllowedFast(u32),
+    Extended {
+        ext_id: u8, // 0 is the extended handshake, others are negotiated per peer
+        payload: Vec<u8>,
+    },
 }
 
 impl PeerMessage {
@@ -46,6 +69,12 @@ impl PeerMessage {
             PeerMessage::Piece { .. } => Some(7),
             PeerMessage::Cancel { .. } => Some(8),
             PeerMessage::Port(_) => Some(9),
+            PeerMessage::SuggestPiece(_) => Some(0x0D),
+            PeerMessage::HaveAll => Some(0x0E),
+            PeerMessage::HaveNone => Some(0x0F),
+            PeerMessage::RejectRequest { .. } => Some(0x10),
+            PeerMessage::AllowedFast(_) => Some(0x11),
+            PeerMessage::Extended { .. } => Some(20),
         }
     }
 }
@@ -0,0 +1,113 @@
+use anyhow::Context;
+use serde_bytes::ByteBuf;
+use serde_derive::{Deserialize, Serialize};
+
+use std::net::SocketAddr;
+
+use crate::peer::PeerAddresses;
+
+/// Peer exchange message (BEP 11), sent as an extended message with the ut_pex id.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct PexMessage {
+    /// Peers connected since the last message, in compact form.
+    #[serde(default)]
+    pub added: PeerAddresses,
+
+    /// One flag byte per added peer (encryption, seed, ...).
+    #[serde(default, rename = "added.f")]
+    pub added_flags: ByteBuf,
+
+    /// Peers disconnected since the last message, in compact form.
+    #[serde(default)]
+    pub dropped: PeerAddresses,
+
+    /// IPv6 peers connected since the last message, in compact form.
+    #[serde(default, deserialize_with = "PeerAddresses::deserialize_v6")]
+    pub added6: PeerAddresses,
+
+    /// One flag byte per added IPv6 peer.
+    #[serde(default, rename = "added6.f")]
+    pub added6_flags: ByteBuf,
+
+    /// IPv6 peers disconnected since the last message, in compact form.
+    #[serde(default, deserialize_with = "PeerAddresses::deserialize_v6")]
+    pub dropped6: PeerAddresses,
+}
+
+impl PexMessage {
+    /// Builds a message from mixed peer lists, putting IPv6 peers in the `added6` and `dropped6`
+    /// fields.
+    pub fn new(added: PeerAddresses, dropped: PeerAddresses) -> Self {
+        let (added, added6) = added.split_by_family();
+        let (dropped, dropped6) = dropped.split_by_family();
+        Self {
+            added_flags: ByteBuf::from(vec![0; added.0.len()]),
+            added6_flags: ByteBuf::from(vec![0; added6.0.len()]),
+            added,
+            dropped,
+            added6,
+            dropped6,
+        }
+    }
+
+    /// Every added peer, IPv4 then IPv6.
+    pub fn added_peers(&self) -> impl Iterator<Item = &SocketAddr> {
+        self.added.iter().chain(self.added6.iter())
+    }
+
+    /// Every dropped peer, IPv4 then IPv6.
+    pub fn dropped_peers(&self) -> impl Iterator<Item = &SocketAddr> {
+        self.dropped.iter().chain(self.dropped6.iter())
+    }
+
+    pub fn from_bytes(payload: &[u8]) -> anyhow::Result<Self> {
+        serde_bencode::from_bytes(payload).context("Failed to parse PEX message")
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        serde_bencode::to_bytes(self).context("Failed to encode PEX message")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_decode_pex_added() {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(b"d5:added12:");
+        payload.extend_from_slice(&[10, 0, 0, 1, 0x1A, 0xE1, 192, 168, 1, 20, 0xC8, 0xD5]);
+        payload.extend_from_slice(b"7:added.f2:");
+        payload.extend_from_slice(&[0x02, 0x00]);
+        payload.extend_from_slice(b"7:dropped0:e");
+
+        let message = PexMessage::from_bytes(&payload).unwrap();
+        assert_eq!(
+            message.added,
+            PeerAddresses(vec![
+                SocketAddr::new(Ipv4Addr::new(10, 0, 0, 1).into(), 6881),
+                SocketAddr::new(Ipv4Addr::new(192, 168, 1, 20).into(), 51413),
+            ])
+        );
+        assert_eq!(message.added_flags.as_slice(), &[0x02, 0x00]);
+        assert!(message.dropped.0.is_empty());
+    }
+
+    #[test]
+    fn test_pex_round_trip() {
+        let added = PeerAddresses(vec![
+            SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 6889),
+            "[::1]:6881".parse().unwrap(),
+        ]);
+        let message = PexMessage::new(added.clone(), PeerAddresses::default());
+        assert_eq!(message.added.0.len(), 1);
+        assert_eq!(message.added6.0.len(), 1);
+
+        let bytes = message.to_bytes().unwrap();
+        let decoded = PexMessage::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, message);
+        assert_eq!(decoded.added_peers().copied().collect::<Vec<_>>(), added.0);
+    }
+}
@@ -1,47 +1,134 @@
 // https://github.com/jonhoo/codecrafters-bittorrent-rust/blob/master/src/tracker.rs
 use crate::peer::PeerAddresses;
 
-use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
 use serde::ser::{Serialize, Serializer};
+use serde_derive::Deserialize;
 use std::fmt;
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
-struct PeerAddressesVisitor;
+/// A single entry of the dictionary model peer list, returned when `compact=0`.
+#[derive(Deserialize)]
+struct PeerDict {
+    /// IP address (dotted quad or IPv6 hexed) or DNS name of the peer.
+    ip: String,
+    port: u16,
+}
+
+/// The `peers` key of an announce response. Dictionary entries given by DNS name are kept
+/// apart as host and port, resolving them while parsing would block the async runtime.
+#[derive(Debug, Default)]
+pub(crate) struct PeerList {
+    pub(crate) addresses: PeerAddresses,
+    pub(crate) hosts: Vec<(String, u16)>,
+}
+
+struct PeerListVisitor;
 
-impl<'de> Visitor<'de> for PeerAddressesVisitor {
-    type Value = PeerAddresses;
+impl<'de> Visitor<'de> for PeerListVisitor {
+    type Value = PeerList;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("6 bytes, the first 4 bytes are a peer's IP address and the last 2 are a peer's port number")
+        formatter.write_str("6 bytes, the first 4 bytes are a peer's IP address and the last 2 are a peer's port number, or a list of peer dictionaries")
     }
 
     fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
     where
         E: de::Error,
     {
-        if v.len() % 6 != 0 {
-            return Err(E::custom(format!("length is {}", v.len())));
+        let addresses = PeerAddresses::from_compact(v)
+            .ok_or_else(|| E::custom(format!("length is {}", v.len())))?;
+        Ok(PeerList {
+            addresses,
+            hosts: Vec::new(),
+        })
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut list = PeerList::default();
+        while let Some(peer) = seq.next_element::<PeerDict>()? {
+            match peer.ip.parse::<IpAddr>() {
+                Ok(ip) => list.addresses.0.push(SocketAddr::new(ip, peer.port)),
+                Err(_) => list.hosts.push((peer.ip, peer.port)),
+            }
+        }
+        Ok(list)
+    }
+}
+
+impl PeerAddresses {
+    /// Parses the compact IPv4 representation, 6 bytes per peer. Returns `None` if the length is
+    /// not a multiple of 6.
+    pub fn from_compact(v: &[u8]) -> Option<Self> {
+        if !v.len().is_multiple_of(6) {
+            return None;
         }
         // TODO: use array_chunks when stable; then we can also pattern-match in closure args
-        Ok(PeerAddresses(
+        Some(PeerAddresses(
             v.chunks_exact(6)
                 .map(|slice_6| {
-                    SocketAddrV4::new(
-                        Ipv4Addr::new(slice_6[0], slice_6[1], slice_6[2], slice_6[3]),
+                    SocketAddr::new(
+                        Ipv4Addr::new(slice_6[0], slice_6[1], slice_6[2], slice_6[3]).into(),
                         u16::from_be_bytes([slice_6[4], slice_6[5]]),
                     )
                 })
                 .collect(),
         ))
     }
+
+    /// Parses the compact IPv6 representation (BEP 7), 18 bytes per peer. Returns `None` if the
+    /// length is not a multiple of 18.
+    pub fn from_compact_v6(v: &[u8]) -> Option<Self> {
+        if !v.len().is_multiple_of(18) {
+            return None;
+        }
+        Some(PeerAddresses(
+            v.chunks_exact(18)
+                .map(|slice_18| {
+                    let octets: [u8; 16] = slice_18[..16].try_into().unwrap();
+                    SocketAddr::new(
+                        Ipv6Addr::from(octets).into(),
+                        u16::from_be_bytes([slice_18[16], slice_18[17]]),
+                    )
+                })
+                .collect(),
+        ))
+    }
+
+    /// Deserializes a compact IPv6 peer list, for the `peers6` and `added6` fields.
+    pub(crate) fn deserialize_v6<'de, D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let bytes = serde_bytes::ByteBuf::deserialize(deserializer)?;
+        PeerAddresses::from_compact_v6(&bytes)
+            .ok_or_else(|| de::Error::custom(format!("length is {}", bytes.len())))
+    }
 }
 
+impl<'de> Deserialize<'de> for PeerList {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_bytes(PeerListVisitor)
+    }
+}
+
+// Only announce responses may name peers by host, every other peer list is compact
 impl<'de> Deserialize<'de> for PeerAddresses {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: Deserializer<'de>,
     {
-        deserializer.deserialize_bytes(PeerAddressesVisitor)
+        let list = PeerList::deserialize(deserializer)?;
+        if !list.hosts.is_empty() {
+            return Err(de::Error::custom("peers must be given by IP address"));
+        }
+        Ok(list.addresses)
     }
 }
 
@@ -50,11 +137,118 @@ impl Serialize for PeerAddresses {
     where
         S: Serializer,
     {
+        // Each address uses the compact form of its family, so lists meant for `peers` and
+        // `peers6` must not be mixed
         let mut single_slice = Vec::with_capacity(6 * self.0.len());
         for peer in &self.0 {
-            single_slice.extend(peer.ip().octets());
+            match peer.ip() {
+                IpAddr::V4(ip) => single_slice.extend(ip.octets()),
+                IpAddr::V6(ip) => single_slice.extend(ip.octets()),
+            }
             single_slice.extend(peer.port().to_be_bytes());
         }
         serializer.serialize_bytes(&single_slice)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::TrackerResponse;
+
+    fn expected_peers() -> PeerAddresses {
+        PeerAddresses(vec![
+            SocketAddr::new(Ipv4Addr::new(192, 0, 2, 123).into(), 6881),
+            SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), 6889),
+        ])
+    }
+
+    #[test]
+    fn test_deserialize_compact_peers() {
+        let mut body = Vec::new();
+        body.extend_from_slice(b"d8:intervali900e5:peers12:");
+        body.extend_from_slice(&[192, 0, 2, 123, 0x1A, 0xE1, 127, 0, 0, 1, 0x1A, 0xE9]);
+        body.extend_from_slice(b"e");
+
+        let response: TrackerResponse = serde_bencode::from_bytes(&body).unwrap();
+        assert_eq!(response.peer_addresses, expected_peers());
+    }
+
+    #[test]
+    fn test_deserialize_dictionary_peers() {
+        let body = b"d8:intervali900e5:peersl\
+            d2:ip11:192.0.2.1237:peer id20:AAAAAAAAAAAAAAAAAAAA4:porti6881ee\
+            d2:ip9:127.0.0.17:peer id20:BBBBBBBBBBBBBBBBBBBB4:porti6889ee\
+            ee";
+
+        let response: TrackerResponse = serde_bencode::from_bytes(body).unwrap();
+        assert_eq!(response.peer_addresses, expected_peers());
+    }
+
+    #[test]
+    fn test_deserialize_dictionary_keeps_hostnames() {
+        let body = b"d8:intervali900e5:peersl\
+            d2:ip16:peer.example.com4:porti6881ee\
+            d2:ip9:127.0.0.14:porti6889ee\
+            ee";
+
+        let response: TrackerResponse = serde_bencode::from_bytes(body).unwrap();
+        assert_eq!(
+            response.peer_addresses,
+            PeerAddresses(vec!["127.0.0.1:6889".parse().unwrap()])
+        );
+        assert_eq!(
+            response.peer_hosts,
+            vec![("peer.example.com".to_string(), 6881)]
+        );
+    }
+
+    #[test]
+    fn test_deserialize_dictionary_ipv6_peer() {
+        let body = b"d8:intervali900e5:peersl\
+            d2:ip11:2001:db8::14:porti6881ee\
+            ee";
+
+        let response: TrackerResponse = serde_bencode::from_bytes(body).unwrap();
+        assert_eq!(
+            response.peer_addresses,
+            PeerAddresses(vec!["[2001:db8::1]:6881".parse().unwrap()])
+        );
+    }
+
+    #[test]
+    fn test_deserialize_mixed_peers_and_peers6() {
+        let mut body = Vec::new();
+        body.extend_from_slice(b"d8:intervali900e5:peers12:");
+        body.extend_from_slice(&[192, 0, 2, 123, 0x1A, 0xE1, 127, 0, 0, 1, 0x1A, 0xE9]);
+        body.extend_from_slice(b"6:peers636:");
+        body.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets());
+        body.extend_from_slice(&[0x1A, 0xE1]);
+        body.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        body.extend_from_slice(&[0x1A, 0xE9]);
+        body.extend_from_slice(b"e");
+
+        let response: TrackerResponse = serde_bencode::from_bytes(&body).unwrap();
+        assert_eq!(response.peer_addresses, expected_peers());
+        assert_eq!(
+            response.peer_addresses6,
+            PeerAddresses(vec![
+                "[2001:db8::1]:6881".parse().unwrap(),
+                "[::1]:6889".parse().unwrap(),
+            ])
+        );
+        assert_eq!(response.peers().count(), 4);
+    }
+
+    #[test]
+    fn test_invalid_compact_v6_length() {
+        let body = b"d8:intervali900e5:peers0:6:peers66:abcdefe";
+        assert!(serde_bencode::from_bytes::<TrackerResponse>(body).is_err());
+    }
+
+    #[test]
+    fn test_invalid_compact_length() {
+        let body = b"d8:intervali900e5:peers5:abcdee";
+        assert!(serde_bencode::from_bytes::<TrackerResponse>(body).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use tokio::time::Duration;
+
+/// How often the unchoke set should be recalculated.
+pub const RECHOKE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Rechoke rounds between optimistic unchoke rotations. Rounds are `RECHOKE_INTERVAL` (10s)
+/// apart, so the optimistic peer rotates every 30 seconds.
+const OPTIMISTIC_UNCHOKE_ROUNDS: usize = 3;
+
+/// Default number of peers unchoked by rate, not counting the optimistic unchoke.
+pub const DEFAULT_UNCHOKE_SLOTS: usize = 4;
+
+/// Recent transfer rate of a connected peer, as seen by the choker.
+#[derive(Debug, Clone, Copy)]
+pub struct PeerRate {
+    pub addr: SocketAddr,
+    /// Bytes per second recently exchanged with the peer, download rate while leeching and
+    /// upload rate while seeding.
+    pub rate: f64,
+    /// Only peers interested in our pieces are worth unchoking.
+    pub interested: bool,
+}
+
+/// Tit-for-tat choking: unchokes the fastest interested peers plus one optimistic unchoke that
+/// gives new peers a chance to prove themselves.
+#[derive(Debug)]
+pub struct Choker {
+    slots: usize,
+    round: usize,
+    optimistic: Option<SocketAddr>,
+}
+
+impl Choker {
+    pub fn new(slots: usize) -> Self {
+        Self {
+            slots,
+            round: 0,
+            optimistic: None,
+        }
+    }
+
+    /// Returns the set of peers to unchoke for the next round, every other peer should be
+    /// choked. Meant to be called every `RECHOKE_INTERVAL`.
+    pub fn rechoke<R: Rng>(&mut self, peers: &[PeerRate], rng: &mut R) -> HashSet<SocketAddr> {
+        let mut interested: Vec<&PeerRate> = peers.iter().filter(|peer| peer.interested).collect();
+        interested.sort_by(|a, b| b.rate.total_cmp(&a.rate));
+
+        let mut unchoked: HashSet<SocketAddr> = interested
+            .iter()
+            .take(self.slots)
+            .map(|peer| peer.addr)
+            .collect();
+
+        let candidates: Vec<SocketAddr> = interested
+            .iter()
+            .map(|peer| peer.addr)
+            .filter(|addr| !unchoked.contains(addr))
+            .collect();
+
+        // Keep the optimistic peer for its whole period unless it left or earned a regular slot
+        let keep_optimistic = !self.round.is_multiple_of(OPTIMISTIC_UNCHOKE_ROUNDS)
+            && self
+                .optimistic
+                .is_some_and(|addr| candidates.contains(&addr));
+        if !keep_optimistic {
+            self.optimistic = candidates.choose(rng).copied();
+        }
+
+        unchoked.extend(self.optimistic);
+        self.round += 1;
+        unchoked
+    }
+}
+
+impl Default for Choker {
+    fn default() -> Self {
+        Self::new(DEFAULT_UNCHOKE_SLOTS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::net::Ipv4Addr;
+
+    fn addr(last_octet: u8) -> SocketAddr {
+        SocketAddr::new(Ipv4Addr::new(10, 0, 0, last_octet).into(), 6881)
+    }
+
+    fn peers() -> Vec<PeerRate> {
+        [
+            (1, 100.0, true),
+            (2, 500.0, true),
+            (3, 50.0, true),
+            (4, 900.0, false), // Fastest, but not interested
+            (5, 300.0, true),
+            (6, 200.0, true),
+            (7, 10.0, true),
+        ]
+        .into_iter()
+        .map(|(octet, rate, interested)| PeerRate {
+            addr: addr(octet),
+            rate,
+            interested,
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_unchokes_fastest_interested_peers() {
+        let mut choker = Choker::new(4);
+        let mut rng = StdRng::seed_from_u64(7);
+
+        let unchoked = choker.rechoke(&peers(), &mut rng);
+
+        assert_eq!(unchoked.len(), 5);
+        for octet in [2, 5, 6, 1] {
+            assert!(unchoked.contains(&addr(octet)));
+        }
+        assert!(!unchoked.contains(&addr(4)));
+
+        let optimistic = choker.optimistic.unwrap();
+        assert!(optimistic == addr(3) || optimistic == addr(7));
+        assert!(unchoked.contains(&optimistic));
+    }
+
+    #[test]
+    fn test_optimistic_unchoke_rotates_every_third_round() {
+        let peers = peers();
+        let mut rotated = false;
+
+        for seed in 0..16 {
+            let mut choker = Choker::new(4);
+            let mut rng = StdRng::seed_from_u64(seed);
+
+            choker.rechoke(&peers, &mut rng);
+            let optimistic = choker.optimistic;
+            for _ in 1..OPTIMISTIC_UNCHOKE_ROUNDS {
+                choker.rechoke(&peers, &mut rng);
+                assert_eq!(choker.optimistic, optimistic);
+            }
+
+            // The fourth round draws again among the same candidates, peers 3 and 7
+            choker.rechoke(&peers, &mut rng);
+            assert!(choker.optimistic == Some(addr(3)) || choker.optimistic == Some(addr(7)));
+            rotated |= choker.optimistic != optimistic;
+        }
+
+        assert!(rotated);
+    }
+
+    #[test]
+    fn test_optimistic_replaced_when_peer_leaves() {
+        let mut choker = Choker::new(1);
+        let mut rng = StdRng::seed_from_u64(3);
+        let peers = peers();
+
+        choker.rechoke(&peers, &mut rng);
+        let optimistic = choker.optimistic.unwrap();
+
+        let remaining: Vec<PeerRate> = peers
+            .into_iter()
+            .filter(|peer| peer.addr != optimistic)
+            .collect();
+        let unchoked = choker.rechoke(&remaining, &mut rng);
+
+        assert!(!unchoked.contains(&optimistic));
+        assert_eq!(unchoked.len(), 2);
+    }
+
+    #[test]
+    fn test_fewer_peers_than_slots() {
+        let mut choker = Choker::default();
+        let mut rng = StdRng::seed_from_u64(0);
+        let peers = &peers()[..2];
+
+        let unchoked = choker.rechoke(peers, &mut rng);
+        assert_eq!(unchoked, HashSet::from([addr(1), addr(2)]));
+        assert_eq!(choker.optimistic, None);
+    }
+}
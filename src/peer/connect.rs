@@ -1,32 +1,253 @@
-use anyhow::{bail, Context, Ok};
-use futures::StreamExt;
+use anyhow::{bail, Context};
+use futures::{SinkExt, StreamExt};
 
-use super::Peer;
-use crate::message::{Bitfield, MessageCodec, PeerMessage};
+use tokio::time::timeout;
+use tracing::instrument;
+
+use super::{EncryptionPolicy, Peer, PeerTransport};
+use crate::message::{Bitfield, MessageCodec, PeerMessage, EXTENDED_HANDSHAKE_ID};
 
 impl Peer {
-    pub async fn connect(&mut self) -> anyhow::Result<&Bitfield> {
+    /// Handshakes and reads the peer's first message to learn what pieces it has: a bitfield,
+    /// or `HaveAll`/`HaveNone` if the fast extension was negotiated. Peers without pieces may
+    /// skip the bitfield and send another message or nothing at all, their bitfield starts
+    /// empty. `total_pieces` is the piece count of the torrent, used to size the bitfield.
+    #[instrument(skip(self), fields(peer = %self.addr))]
+    pub async fn connect(&mut self, total_pieces: usize) -> anyhow::Result<&Bitfield> {
+        if self.encryption != EncryptionPolicy::Disabled {
+            let transport = self.connect_encrypted().await?;
+            return self.connect_over(transport, total_pieces).await;
+        }
+
         let tcp_stream = self.handshake().await.context("Failed to handshake")?;
-        let mut frame = tokio_util::codec::Framed::new(tcp_stream, MessageCodec);
+        self.receive_first_message(Box::new(tcp_stream), total_pieces)
+            .await
+    }
 
-        let bitfield = frame
-            .next()
+    /// Same as `connect`, over `transport` instead of a new TCP connection.
+    pub async fn connect_over<T>(
+        &mut self,
+        mut transport: T,
+        total_pieces: usize,
+    ) -> anyhow::Result<&Bitfield>
+    where
+        T: PeerTransport + 'static,
+    {
+        self.handshake_over(&mut transport)
+            .await
+            .context("Failed to handshake")?;
+        self.receive_first_message(Box::new(transport), total_pieces)
             .await
-            .context("Failed to get the next TCP frame")?
-            .context("Failed to receive bitfield")?;
+    }
 
-        match bitfield {
-            PeerMessage::Bitfield(data) => {
-                self.bitfield = Some(Bitfield::from_bytes(data));
+    async fn receive_first_message(
+        &mut self,
+        transport: Box<dyn PeerTransport>,
+        total_pieces: usize,
+    ) -> anyhow::Result<&Bitfield> {
+        let mut frame = tokio_util::codec::Framed::new(transport, MessageCodec);
+
+        let first = match timeout(self.timeout, frame.next()).await {
+            Ok(message) => Some(
+                message
+                    .context("Peer closed the connection after the handshake")?
+                    .context("Failed to receive first message")?,
+            ),
+            Err(_) => None,
+        };
+
+        let mut bitfield = Bitfield::new(total_pieces);
+        match first {
+            Some(PeerMessage::Bitfield(data)) => bitfield = Bitfield::from_bytes(data),
+            Some(PeerMessage::HaveAll) if self.supports_fast_extension() => {
+                bitfield = Bitfield::full(total_pieces);
             }
-            _ => {
-                bail!("First message is not bitfield");
+            Some(PeerMessage::HaveNone) if self.supports_fast_extension() => {}
+            Some(PeerMessage::HaveAll | PeerMessage::HaveNone) => {
+                bail!("Peer sent a fast extension message without negotiating it");
             }
+            Some(PeerMessage::Have(index)) => bitfield.set_piece(index as usize),
+            Some(PeerMessage::Choke) => self.state.choke(),
+            Some(PeerMessage::Unchoke) => self.state.unchoke(),
+            Some(PeerMessage::Extended {
+                ext_id: EXTENDED_HANDSHAKE_ID,
+                payload,
+            }) => self.handle_extended_handshake(&payload)?,
+            Some(message) => {
+                tracing::debug!(?message, "Ignoring message before any bitfield");
+            }
+            None => tracing::debug!("Peer sent no bitfield"),
         }
 
-        self.tcp_stream = Some(frame);
+        self.bitfield = Some(bitfield);
+        self.total_pieces = total_pieces;
+        self.stream = Some(frame);
 
         self.bitfield()
             .context("Bitfield was not set after successful connection")
     }
+
+    /// Sends a message over the connection opened by `connect`.
+    pub async fn send_message(&mut self, message: PeerMessage) -> anyhow::Result<()> {
+        let frame = self.stream.as_mut().context("Peer is not connected")?;
+        frame
+            .send(message)
+            .await
+            .context("Failed to send message to peer")
+    }
+
+    /// Tells the peer which pieces we have. Meant to be sent right after `connect`. With the
+    /// fast extension negotiated, no or all pieces are sent as `HaveNone` or `HaveAll`, which
+    /// BEP 6 requires in place of a missing bitfield. Otherwise nothing is sent when we have no
+    /// pieces, since the bitfield message is optional in that case.
+    pub async fn send_bitfield(&mut self, bitfield: &Bitfield) -> anyhow::Result<()> {
+        let count = bitfield.count_set();
+        if self.supports_fast_extension() {
+            if count == 0 {
+                return self.send_message(PeerMessage::HaveNone).await;
+            }
+            if count == self.total_pieces {
+                return self.send_message(PeerMessage::HaveAll).await;
+            }
+        } else if count == 0 {
+            return Ok(());
+        }
+
+        self.send_message(PeerMessage::Bitfield(bitfield.as_bytes().to_vec()))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::InfoHash;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+    use tokio_util::codec::Framed;
+
+    const INFO_HASH: InfoHash = InfoHash::new([5; 20]);
+    const PEER_ID: &str = "-TR0001-connecttest0";
+
+    /// Remote peer echoing our handshake (so with the same reserved bits) under another peer id,
+    /// then sending `first` and returning the first message it receives, if any.
+    async fn remote_peer(
+        first: PeerMessage,
+    ) -> anyhow::Result<(SocketAddr, JoinHandle<Option<PeerMessage>>)> {
+        remote_peer_with(first, true).await
+    }
+
+    /// Same as `remote_peer`, clearing the fast extension bit of the echoed handshake unless
+    /// `fast` is set.
+    async fn remote_peer_with(
+        first: PeerMessage,
+        fast: bool,
+    ) -> anyhow::Result<(SocketAddr, JoinHandle<Option<PeerMessage>>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let remote = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut handshake = vec![0u8; 68];
+            stream.read_exact(&mut handshake).await.unwrap();
+
+            handshake[48..68].copy_from_slice(&[9; 20]);
+            if !fast {
+                handshake[27] &= !0x04;
+            }
+            stream.write_all(&handshake).await.unwrap();
+
+            let mut frame = Framed::new(stream, MessageCodec);
+            frame.send(first).await.unwrap();
+            frame.next().await.and_then(Result::ok)
+        });
+
+        Ok((addr, remote))
+    }
+
+    #[tokio::test]
+    async fn test_connect_have_all() -> anyhow::Result<()> {
+        let (addr, _) = remote_peer(PeerMessage::HaveAll).await?;
+        let mut peer = Peer::new(addr, INFO_HASH, PEER_ID.parse().unwrap());
+
+        let bitfield = peer.connect(10).await?;
+        assert_eq!(bitfield.count_set(), 10);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_have_none() -> anyhow::Result<()> {
+        let (addr, _) = remote_peer(PeerMessage::HaveNone).await?;
+        let mut peer = Peer::new(addr, INFO_HASH, PEER_ID.parse().unwrap());
+
+        let bitfield = peer.connect(10).await?;
+        assert_eq!(bitfield.count_set(), 0);
+        assert_eq!(bitfield.as_bytes().len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_without_bitfield() -> anyhow::Result<()> {
+        let (addr, _) = remote_peer(PeerMessage::Unchoke).await?;
+        let mut peer = Peer::new(addr, INFO_HASH, PEER_ID.parse().unwrap());
+
+        let bitfield = peer.connect(10).await?;
+        assert_eq!(bitfield.count_set(), 0);
+        assert!(!peer.is_choked());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_connect_have_instead_of_bitfield() -> anyhow::Result<()> {
+        let (addr, _) = remote_peer(PeerMessage::Have(3)).await?;
+        let mut peer = Peer::new(addr, INFO_HASH, PEER_ID.parse().unwrap());
+
+        let bitfield = peer.connect(10).await?;
+        assert!(bitfield.has_piece(3));
+        assert_eq!(bitfield.count_set(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_bitfield() -> anyhow::Result<()> {
+        let (addr, remote) = remote_peer_with(PeerMessage::Bitfield(vec![0, 0]), false).await?;
+        let mut peer = Peer::new(addr, INFO_HASH, PEER_ID.parse().unwrap());
+        peer.connect(10).await?;
+        assert!(!peer.supports_fast_extension());
+
+        // Nothing to announce, so no message goes out
+        peer.send_bitfield(&Bitfield::new(10)).await?;
+
+        let mut ours = Bitfield::new(10);
+        ours.set_piece(0);
+        ours.set_piece(9);
+        peer.send_bitfield(&ours).await?;
+
+        assert_eq!(
+            remote.await?,
+            Some(PeerMessage::Bitfield(vec![0b1000_0000, 0b0100_0000]))
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_send_bitfield_fast_extension() -> anyhow::Result<()> {
+        let cases = [
+            (Bitfield::new(10), PeerMessage::HaveNone),
+            (Bitfield::full(10), PeerMessage::HaveAll),
+        ];
+
+        for (ours, expected) in cases {
+            let (addr, remote) = remote_peer(PeerMessage::Bitfield(vec![0, 0])).await?;
+            let mut peer = Peer::new(addr, INFO_HASH, PEER_ID.parse().unwrap());
+            peer.connect(10).await?;
+            assert!(peer.supports_fast_extension());
+
+            peer.send_bitfield(&ours).await?;
+            assert_eq!(remote.await?, Some(expected));
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,144 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+
+use anyhow::{bail, Context};
+use tokio::time::{Duration, Instant};
+
+use super::{Peer, PeerAddresses};
+use crate::message::{ExtendedHandshake, PeerMessage, PexMessage, EXTENDED_HANDSHAKE_ID};
+
+/// Minimum time between two PEX messages sent to the same peer, as required by BEP 11.
+pub const PEX_INTERVAL: Duration = Duration::from_secs(60);
+
+impl Peer {
+    /// Sends our extended handshake (BEP 10), advertising the extensions we support. Only valid
+    /// after `connect` and if the remote supports the extension protocol. Pass `private` for
+    /// private torrents so PEX isn't offered.
+    pub async fn send_extended_handshake(
+        &mut self,
+        listen_port: Option<u16>,
+        private: bool,
+    ) -> anyhow::Result<()> {
+        if !self.supports_extension_protocol() {
+            bail!("Peer does not support the extension protocol");
+        }
+
+        let payload = ExtendedHandshake::new(listen_port, private).to_bytes()?;
+        self.send_extended(EXTENDED_HANDSHAKE_ID, payload)
+            .await
+            .context("Failed to send extended handshake")
+    }
+
+    /// Records the extensions the remote advertised in its extended handshake.
+    pub fn handle_extended_handshake(&mut self, payload: &[u8]) -> anyhow::Result<()> {
+        self.remote_extensions = Some(ExtendedHandshake::from_bytes(payload)?);
+        Ok(())
+    }
+
+    /// The remote's extended handshake, `None` until it has been received.
+    pub fn remote_extensions(&self) -> Option<&ExtendedHandshake> {
+        self.remote_extensions.as_ref()
+    }
+
+    /// Parses an incoming PEX message and returns the added peers not yet in `known_peers`,
+    /// which are inserted so later messages don't report them again. Refused for private
+    /// torrents, whose peers must only come from the tracker (BEP 27).
+    pub fn handle_pex(
+        &self,
+        payload: &[u8],
+        known_peers: &mut HashSet<SocketAddr>,
+        private: bool,
+    ) -> anyhow::Result<Vec<SocketAddr>> {
+        if private {
+            bail!("PEX is disabled for private torrents");
+        }
+
+        let message = PexMessage::from_bytes(payload)?;
+        Ok(message
+            .added_peers()
+            .filter(|addr| **addr != self.addr && known_peers.insert(**addr))
+            .copied()
+            .collect())
+    }
+
+    /// Sends a PEX update unless one was sent less than `PEX_INTERVAL` ago. Returns whether the
+    /// message was sent. Refused for private torrents.
+    pub async fn send_pex(
+        &mut self,
+        added: PeerAddresses,
+        dropped: PeerAddresses,
+        private: bool,
+    ) -> anyhow::Result<bool> {
+        if private {
+            bail!("PEX is disabled for private torrents");
+        }
+
+        if self
+            .last_pex
+            .is_some_and(|last| last.elapsed() < PEX_INTERVAL)
+        {
+            return Ok(false);
+        }
+
+        let ext_id = self
+            .remote_extensions
+            .as_ref()
+            .and_then(|extensions| extensions.extension_id("ut_pex"))
+            .context("Peer does not support ut_pex")?;
+
+        let payload = PexMessage::new(added, dropped).to_bytes()?;
+        self.send_extended(ext_id, payload)
+            .await
+            .context("Failed to send PEX message")?;
+        self.last_pex = Some(Instant::now());
+        Ok(true)
+    }
+
+    pub(super) async fn send_extended(
+        &mut self,
+        ext_id: u8,
+        payload: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        self.send_message(PeerMessage::Extended { ext_id, payload })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::InfoHash;
+    use std::net::Ipv4Addr;
+
+    fn peer() -> Peer {
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 6881);
+        Peer::new(
+            addr,
+            InfoHash::new([0; 20]),
+            "-TR0001-pextestpeer0".parse().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_pex_refused_for_private_torrents() {
+        let added = SocketAddr::new(Ipv4Addr::new(192, 0, 2, 1).into(), 6881);
+        let payload = PexMessage::new(PeerAddresses(vec![added]), PeerAddresses(vec![]))
+            .to_bytes()
+            .unwrap();
+
+        let mut known_peers = HashSet::new();
+        assert!(peer().handle_pex(&payload, &mut known_peers, true).is_err());
+        assert!(known_peers.is_empty());
+        assert_eq!(
+            peer()
+                .handle_pex(&payload, &mut known_peers, false)
+                .unwrap(),
+            vec![added]
+        );
+
+        let sent = peer()
+            .send_pex(PeerAddresses(vec![added]), PeerAddresses(vec![]), true)
+            .await;
+        assert!(sent.is_err());
+    }
+}
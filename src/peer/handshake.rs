@@ -1,16 +1,35 @@
-use super::Peer;
-use anyhow::{bail, Context, Ok};
+use super::{EncryptionPolicy, Peer, PeerId, PeerTransport};
+use crate::message::{Bitfield, MessageCodec};
+use crate::torrent::InfoHash;
+use anyhow::{bail, Context};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
     time::timeout,
     time::Duration,
 };
+use tokio_util::codec::Framed;
 use tracing::instrument;
 
 const PROTOCOL_IDENTIFIER_LENGTH: u8 = 19;
 const PROTOCOL_IDENTIFIER: [u8; 19] = *b"BitTorrent protocol";
 const HANDSHAKE_MESSAGE_LENGTH: usize = 68;
 
+// Reserved bit 20 from the right, signals support for the extension protocol (BEP 10)
+const EXTENSION_PROTOCOL_BYTE: usize = 5;
+const EXTENSION_PROTOCOL_BIT: u8 = 0x10;
+
+// Third least significant bit, signals support for the fast extension (BEP 6)
+const FAST_EXTENSION_BYTE: usize = 7;
+const FAST_EXTENSION_BIT: u8 = 0x04;
+
+/// Default limit for establishing the TCP connection and for the handshake response.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Retry delays start at 500ms and double up to 8s
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(8);
+
 #[derive(Copy, Clone)]
 struct HandshakeMessage {
     length: u8,
@@ -33,45 +52,127 @@ impl HandshakeMessage {
 }
 
 impl Peer {
-    //TODO: retry mechanism with exponential backoff
-    #[instrument(skip(self))]
-    pub async fn handshake(&self) -> anyhow::Result<tokio::net::TcpStream> {
-        if self.peer_id.as_bytes().len() != 20 {
-            bail!("Peer ID must be exactly 20 bytes long");
+    /// Retries `handshake` up to `max_attempts` times with exponential backoff. Only connection
+    /// and timeout errors are retried, a peer that answers with the wrong protocol or info hash
+    /// fails immediately.
+    #[instrument(skip(self), fields(peer = %self.addr))]
+    pub async fn handshake_with_retry(&mut self, max_attempts: usize) -> anyhow::Result<TcpStream> {
+        let mut delay = INITIAL_RETRY_DELAY;
+        let mut attempt = 1;
+
+        loop {
+            match self.handshake().await {
+                Ok(tcp_stream) => return Ok(tcp_stream),
+                Err(e) if attempt < max_attempts && is_retryable(&e) => {
+                    tracing::debug!(attempt, ?delay, "Handshake failed, retrying: {:#}", e);
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(e).context(format!("Handshake failed after {} attempts", attempt))
+                }
+            }
         }
+    }
 
-        let mut tcp_stream = timeout(
-            Duration::from_secs(5),
-            tokio::net::TcpStream::connect(self.addr),
-        )
-        .await
-        .context("Establishing TCP stream timed out after 5s")?
-        .context("Failed to connect to TCP stream")?;
+    #[instrument(skip(self), fields(peer = %self.addr))]
+    pub async fn handshake(&mut self) -> anyhow::Result<TcpStream> {
+        let mut tcp_stream = self.connect_tcp().await?;
+        self.handshake_over(&mut tcp_stream).await?;
+        Ok(tcp_stream)
+    }
 
-        let mut info_hash = [0u8; 20];
-        info_hash.copy_from_slice(&self.info_hash);
+    /// Handshakes over an already open stream, we speak first.
+    pub async fn handshake_over<S>(&mut self, stream: &mut S) -> anyhow::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.send_handshake(stream).await?;
+        self.receive_handshake(stream).await?;
 
-        let mut peer_id = [0u8; 20];
-        peer_id.copy_from_slice(self.peer_id.as_bytes());
+        tracing::info!(peer = %self.addr, "Handshake sucessful");
+        Ok(())
+    }
 
-        let handshake_message = HandshakeMessage {
+    /// Completes the handshake for a connection a remote peer opened to us. The remote speaks
+    /// first, and we only answer if it asked for our info hash. The returned peer is connected,
+    /// with an empty bitfield of `total_pieces` until the remote sends its own. Unless
+    /// `encryption` is `Disabled`, the remote may run the MSE handshake first.
+    #[instrument(skip(tcp_stream, info_hash, peer_id))]
+    pub async fn accept(
+        tcp_stream: TcpStream,
+        info_hash: InfoHash,
+        peer_id: PeerId,
+        total_pieces: usize,
+        encryption: EncryptionPolicy,
+    ) -> anyhow::Result<Self> {
+        let addr = tcp_stream
+            .peer_addr()
+            .context("Failed to get remote address")?;
+
+        let mut peer = Peer::new(addr, info_hash, peer_id).with_encryption(encryption);
+        let mut transport: Box<dyn PeerTransport> = if encryption != EncryptionPolicy::Disabled {
+            peer.accept_encrypted(tcp_stream).await?
+        } else {
+            Box::new(tcp_stream)
+        };
+        peer.receive_handshake(&mut transport).await?;
+        peer.send_handshake(&mut transport).await?;
+
+        peer.bitfield = Some(Bitfield::new(total_pieces));
+        peer.total_pieces = total_pieces;
+        peer.stream = Some(Framed::new(transport, MessageCodec));
+
+        tracing::info!(peer = %addr, "Accepted handshake");
+        Ok(peer)
+    }
+
+    pub(super) async fn connect_tcp(&self) -> anyhow::Result<TcpStream> {
+        timeout(self.timeout, TcpStream::connect(self.addr))
+            .await
+            .context(format!(
+                "Establishing TCP stream timed out after {:?}",
+                self.timeout
+            ))?
+            .context("Failed to connect to TCP stream")
+    }
+
+    fn handshake_message(&self) -> HandshakeMessage {
+        let mut reserved = [0u8; 8];
+        reserved[EXTENSION_PROTOCOL_BYTE] |= EXTENSION_PROTOCOL_BIT;
+        reserved[FAST_EXTENSION_BYTE] |= FAST_EXTENSION_BIT;
+
+        HandshakeMessage {
             length: PROTOCOL_IDENTIFIER_LENGTH,
             pstr: PROTOCOL_IDENTIFIER,
-            reserved: [0; 8],
-            info_hash,
-            peer_id,
-        };
+            reserved,
+            info_hash: *self.info_hash.as_bytes(),
+            peer_id: *self.peer_id.as_bytes(),
+        }
+    }
 
-        tcp_stream
-            .write_all(&handshake_message.to_bytes())
+    async fn send_handshake<S>(&self, stream: &mut S) -> anyhow::Result<()>
+    where
+        S: AsyncWrite + Unpin,
+    {
+        stream
+            .write_all(&self.handshake_message().to_bytes())
             .await
-            .context("Failed to send handshake message!")?;
+            .context("Failed to send handshake message!")
+    }
 
-        // Read the response
+    async fn receive_handshake<S>(&mut self, stream: &mut S) -> anyhow::Result<()>
+    where
+        S: AsyncRead + Unpin,
+    {
         let mut response = vec![0u8; HANDSHAKE_MESSAGE_LENGTH];
-        timeout(Duration::from_secs(5), tcp_stream.read_exact(&mut response))
+        timeout(self.timeout, stream.read_exact(&mut response))
             .await
-            .context("Handshake response timed out after 5s")?
+            .context(format!(
+                "Handshake response timed out after {:?}",
+                self.timeout
+            ))?
             .context("Failed to read handshake response")?;
 
         // Validate the response
@@ -79,18 +180,212 @@ impl Peer {
             bail!("Invalid protocol identifier in handshake response");
         }
 
-        if response[28..48] != info_hash {
+        if response[28..48] != *self.info_hash.as_bytes() {
             bail!("Info hash mismatch in handshake response");
         }
 
-        tracing::info!("Handshake with peer {} sucessful", self.addr);
-        Ok(tcp_stream)
+        let mut remote_reserved = [0u8; 8];
+        remote_reserved.copy_from_slice(&response[20..28]);
+        self.remote_reserved = remote_reserved;
+
+        let mut remote_peer_id = [0u8; 20];
+        remote_peer_id.copy_from_slice(&response[48..68]);
+        self.remote_peer_id = Some(remote_peer_id);
+
+        // Trackers may hand out our own address
+        if &remote_peer_id == self.peer_id.as_bytes() {
+            bail!("Connected to ourselves");
+        }
+
+        Ok(())
     }
 }
 
+impl Peer {
+    /// Whether the remote set the extension protocol bit in its handshake.
+    pub fn supports_extension_protocol(&self) -> bool {
+        self.remote_reserved[EXTENSION_PROTOCOL_BYTE] & EXTENSION_PROTOCOL_BIT != 0
+    }
+
+    /// Whether the remote set the fast extension bit in its handshake.
+    pub fn supports_fast_extension(&self) -> bool {
+        self.remote_reserved[FAST_EXTENSION_BYTE] & FAST_EXTENSION_BIT != 0
+    }
+}
+
+/// Connection and timeout failures are transient, anything else is a protocol error.
+fn is_retryable(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|cause| cause.is::<std::io::Error>() || cause.is::<tokio::time::error::Elapsed>())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    const PEER_ID: &str = "-TR0001-abcdefghijkl";
+
+    fn response(info_hash: [u8; 20], peer_id: [u8; 20]) -> Vec<u8> {
+        HandshakeMessage {
+            length: PROTOCOL_IDENTIFIER_LENGTH,
+            pstr: PROTOCOL_IDENTIFIER,
+            reserved: [0; 8],
+            info_hash,
+            peer_id,
+        }
+        .to_bytes()
+    }
+
+    /// Listener that drops the first `failures` connections and answers the rest with a
+    /// handshake for `info_hash`.
+    async fn flaky_listener(
+        failures: usize,
+        info_hash: [u8; 20],
+    ) -> (SocketAddr, Arc<AtomicUsize>) {
+        listener_with_peer_id(failures, info_hash, [9; 20]).await
+    }
+
+    async fn listener_with_peer_id(
+        failures: usize,
+        info_hash: [u8; 20],
+        peer_id: [u8; 20],
+    ) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                if counter.fetch_add(1, Ordering::SeqCst) < failures {
+                    drop(stream);
+                    continue;
+                }
+                let mut request = vec![0u8; HANDSHAKE_MESSAGE_LENGTH];
+                stream.read_exact(&mut request).await.unwrap();
+                stream
+                    .write_all(&response(info_hash, peer_id))
+                    .await
+                    .unwrap();
+            }
+        });
+
+        (addr, connections)
+    }
+
+    #[test]
+    fn test_handshake_advertises_extensions() {
+        let peer = Peer::new(
+            SocketAddr::new(std::net::Ipv4Addr::LOCALHOST.into(), 6881),
+            [1; 20].into(),
+            PEER_ID.parse().unwrap(),
+        );
+        let bytes = peer.handshake_message().to_bytes();
+        assert_eq!(&bytes[20..28], &[0, 0, 0, 0, 0, 0x10, 0, 0x04]);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_captures_remote_peer_id() {
+        let info_hash = [1; 20];
+        let (addr, _) = flaky_listener(0, info_hash).await;
+        let mut peer = Peer::new(addr, info_hash.into(), PEER_ID.parse().unwrap());
+
+        assert_eq!(peer.remote_peer_id(), None);
+        peer.handshake().await.unwrap();
+        assert_eq!(peer.remote_peer_id(), Some(&[9; 20]));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_over_ipv6() {
+        let listener = match TcpListener::bind("[::1]:0").await {
+            Ok(listener) => listener,
+            // IPv6 may be disabled on the host
+            Err(_) => return,
+        };
+        let addr = listener.local_addr().unwrap();
+        let info_hash = [1; 20];
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![0u8; HANDSHAKE_MESSAGE_LENGTH];
+            stream.read_exact(&mut request).await.unwrap();
+            stream
+                .write_all(&response(info_hash, [9; 20]))
+                .await
+                .unwrap();
+        });
+
+        let mut peer = Peer::new(addr, info_hash.into(), PEER_ID.parse().unwrap());
+        peer.handshake().await.unwrap();
+        assert_eq!(peer.remote_peer_id(), Some(&[9; 20]));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_uses_configured_timeout() {
+        // Accepts the connection but never answers the handshake
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let mut peer = Peer::new(addr, [1; 20].into(), PEER_ID.parse().unwrap())
+            .with_timeout(Duration::from_millis(100));
+        let started = std::time::Instant::now();
+
+        let error = peer.handshake().await.unwrap_err();
+        assert!(format!("{:#}", error).contains("timed out after 100ms"));
+        assert!(started.elapsed() < DEFAULT_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejects_self_connection() {
+        let info_hash = [1; 20];
+        let own_id: [u8; 20] = PEER_ID.as_bytes().try_into().unwrap();
+        let (addr, connections) = listener_with_peer_id(0, info_hash, own_id).await;
+        let mut peer = Peer::new(addr, info_hash.into(), PEER_ID.parse().unwrap());
+
+        let error = peer.handshake_with_retry(3).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("Connected to ourselves"));
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_retry_succeeds_after_failures() {
+        let info_hash = [1; 20];
+        let (addr, connections) = flaky_listener(2, info_hash).await;
+        let mut peer = Peer::new(addr, info_hash.into(), PEER_ID.parse().unwrap());
+
+        assert!(peer.handshake_with_retry(3).await.is_ok());
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_retry_gives_up() {
+        let info_hash = [1; 20];
+        let (addr, connections) = flaky_listener(usize::MAX, info_hash).await;
+        let mut peer = Peer::new(addr, info_hash.into(), PEER_ID.parse().unwrap());
+
+        assert!(peer.handshake_with_retry(2).await.is_err());
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_retry_does_not_retry_info_hash_mismatch() {
+        let (addr, connections) = flaky_listener(0, [2; 20]).await;
+        let mut peer = Peer::new(addr, [1; 20].into(), PEER_ID.parse().unwrap());
+
+        let error = peer.handshake_with_retry(3).await.unwrap_err();
+        assert!(format!("{:#}", error).contains("Info hash mismatch"));
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
 
     #[test]
     fn test_handshake_message_serialization() {
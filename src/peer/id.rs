@@ -0,0 +1,76 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::bail;
+
+/// The 20 byte id a client sends in its handshake and tracker announces, checked once when
+/// it is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PeerId([u8; 20]);
+
+impl PeerId {
+    pub const fn new(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    /// Every byte percent-encoded, for the `peer_id` query parameter of tracker requests.
+    pub fn url_encode(&self) -> String {
+        let mut encoded = String::with_capacity(self.0.len() * 3);
+        for byte in self.0 {
+            encoded.push('%');
+            encoded.push_str(&format!("{:02X}", byte));
+        }
+        encoded
+    }
+}
+
+impl FromStr for PeerId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes().try_into() {
+            Ok(bytes) => Ok(Self(bytes)),
+            Err(_) => bail!("Peer ID must be exactly 20 bytes long, got {}", s.len()),
+        }
+    }
+}
+
+impl From<[u8; 20]> for PeerId {
+    fn from(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_requires_20_bytes() {
+        let id: PeerId = "-TR0001-abcdefghijkl".parse().unwrap();
+        assert_eq!(id.as_bytes(), b"-TR0001-abcdefghijkl");
+        assert_eq!(id.to_string(), "-TR0001-abcdefghijkl");
+
+        assert!("-TR0001-abcdefghijk".parse::<PeerId>().is_err());
+        assert!("-TR0001-abcdefghijklm".parse::<PeerId>().is_err());
+    }
+
+    #[test]
+    fn test_url_encode_keeps_raw_bytes() {
+        let mut bytes = *b"-TR0001-abcdefghijkl";
+        bytes[19] = 0xFF;
+        let encoded = PeerId::new(bytes).url_encode();
+        assert!(encoded.starts_with("%2D%54%52"));
+        assert!(encoded.ends_with("%6B%FF"));
+    }
+}
@@ -0,0 +1,66 @@
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+
+use super::{EncryptionPolicy, Peer, PeerId};
+use crate::torrent::InfoHash;
+
+// Accept errors such as running out of file descriptors persist for a while, retrying
+// immediately would spin
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+impl Peer {
+    /// Spawns a task accepting inbound connections on `listener`. Each connection is handshaked
+    /// on its own task, and peers that asked for `info_hash` are sent on `peers_tx`, connected
+    /// and ready to exchange messages. `total_pieces` sizes their bitfields, and `encryption`
+    /// decides whether inbound connections may or must be encrypted.
+    ///
+    /// The task stops when `shutdown` fires or the receiving end of `peers_tx` is dropped.
+    pub fn spawn_listener(
+        listener: TcpListener,
+        info_hash: InfoHash,
+        peer_id: PeerId,
+        total_pieces: usize,
+        encryption: EncryptionPolicy,
+        peers_tx: mpsc::Sender<Peer>,
+        mut shutdown: broadcast::Receiver<()>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let (tcp_stream, addr) = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            warn!("Failed to accept connection: {}", e);
+                            sleep(ACCEPT_RETRY_DELAY).await;
+                            continue;
+                        }
+                    },
+                    _ = shutdown.recv() => {
+                        info!("Stopping peer listener");
+                        return;
+                    }
+                };
+
+                if peers_tx.is_closed() {
+                    return;
+                }
+
+                let peers_tx = peers_tx.clone();
+                tokio::spawn(async move {
+                    let accepted =
+                        Peer::accept(tcp_stream, info_hash, peer_id, total_pieces, encryption)
+                            .await;
+                    match accepted {
+                        Ok(accepted) => {
+                            let _ = peers_tx.send(accepted).await;
+                        }
+                        Err(e) => warn!("Inbound handshake from {} failed: {:#}", addr, e),
+                    }
+                });
+            }
+        })
+    }
+}
@@ -0,0 +1,257 @@
+use anyhow::{bail, Context};
+use tracing::instrument;
+
+use super::Peer;
+use crate::message::{
+    ExtendedHandshake, MetadataMessage, PeerMessage, EXTENDED_HANDSHAKE_ID, METADATA_PIECE_SIZE,
+    UT_METADATA_ID,
+};
+use crate::torrent::{Magnet, Torrent};
+
+// Real info dictionaries are a few MB at most, anything larger is refused
+const MAX_METADATA_SIZE: usize = 16 * 1024 * 1024;
+
+impl Peer {
+    /// Downloads the info dictionary of `magnet` from the peer (BEP 9) and builds the torrent
+    /// from it. Only valid after `connect`, which can be given 0 pieces as the piece count isn't
+    /// known yet. The peer must support the extension protocol and ut_metadata.
+    #[instrument(skip(self, magnet), fields(peer = %self.addr))]
+    pub async fn fetch_metadata(&mut self, magnet: &Magnet) -> anyhow::Result<Torrent> {
+        if !self.supports_extension_protocol() {
+            bail!("Peer does not support the extension protocol");
+        }
+
+        // Whether the torrent is private isn't known yet, so PEX is left out
+        let mut handshake = ExtendedHandshake::new(None, true);
+        handshake
+            .m
+            .insert("ut_metadata".to_string(), UT_METADATA_ID as i64);
+        self.send_extended(EXTENDED_HANDSHAKE_ID, handshake.to_bytes()?)
+            .await
+            .context("Failed to send extended handshake")?;
+
+        while self.remote_extensions.is_none() {
+            if let PeerMessage::Extended {
+                ext_id: EXTENDED_HANDSHAKE_ID,
+                payload,
+            } = self.receive_message().await?
+            {
+                self.handle_extended_handshake(&payload)?;
+            }
+        }
+
+        let (ext_id, size) = self.remote_metadata()?;
+        if size == 0 || size > MAX_METADATA_SIZE {
+            bail!("Peer advertised metadata of {} bytes", size);
+        }
+
+        let mut metadata = Vec::with_capacity(size);
+        for piece in 0..size.div_ceil(METADATA_PIECE_SIZE) {
+            self.send_extended(ext_id, MetadataMessage::Request { piece }.to_bytes()?)
+                .await
+                .context("Failed to request metadata")?;
+
+            let data = self.receive_metadata_piece(piece).await?;
+            let expected = (size - piece * METADATA_PIECE_SIZE).min(METADATA_PIECE_SIZE);
+            if data.len() != expected {
+                bail!(
+                    "Metadata piece {} is {} bytes, expected {}",
+                    piece,
+                    data.len(),
+                    expected
+                );
+            }
+            metadata.extend_from_slice(&data);
+        }
+
+        tracing::debug!(size, "Received metadata");
+        Torrent::from_metadata(magnet, &metadata)
+    }
+
+    /// The id to send ut_metadata messages with and the metadata size the remote advertised.
+    fn remote_metadata(&self) -> anyhow::Result<(u8, usize)> {
+        let extensions = self
+            .remote_extensions
+            .as_ref()
+            .context("Peer sent no extended handshake")?;
+        let ext_id = extensions
+            .extension_id("ut_metadata")
+            .context("Peer does not support ut_metadata")?;
+        let size = extensions
+            .metadata_size
+            .context("Peer did not advertise the metadata size")?;
+        Ok((ext_id, size))
+    }
+
+    async fn receive_metadata_piece(&mut self, piece: usize) -> anyhow::Result<Vec<u8>> {
+        loop {
+            let PeerMessage::Extended { ext_id, payload } = self.receive_message().await? else {
+                continue;
+            };
+
+            match ext_id {
+                EXTENDED_HANDSHAKE_ID => self.handle_extended_handshake(&payload)?,
+                UT_METADATA_ID => match MetadataMessage::from_bytes(&payload)? {
+                    MetadataMessage::Data {
+                        piece: received,
+                        data,
+                        ..
+                    } if received == piece => return Ok(data),
+                    MetadataMessage::Reject { piece: rejected } if rejected == piece => {
+                        bail!("Peer rejected metadata piece {}", piece)
+                    }
+                    MetadataMessage::Request { piece: requested } => {
+                        // We're fetching the metadata ourselves, so have none to share
+                        let (remote_id, _) = self.remote_metadata()?;
+                        let reject = MetadataMessage::Reject { piece: requested };
+                        self.send_extended(remote_id, reject.to_bytes()?).await?;
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::MessageCodec;
+    use crate::torrent::{Hashes, Info, InfoHash, Keys};
+    use futures::{SinkExt, StreamExt};
+    use sha1::{Digest, Sha1};
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio_util::codec::Framed;
+
+    const PEER_ID: &str = "-TR0001-metadatatest";
+    const REMOTE_METADATA_ID: u8 = 3;
+
+    /// Info dictionary large enough to span two metadata pieces.
+    fn info_bytes() -> Vec<u8> {
+        let info = Info {
+            name: "metadata".to_string(),
+            piece_length: 16 * 1024,
+            pieces: Hashes(vec![[1; 20]; 1000]),
+            private: None,
+            keys: Keys::SingleFile {
+                length: 1000 * 16 * 1024,
+            },
+            extra: Default::default(),
+        };
+        serde_bencode::to_bytes(&info).unwrap()
+    }
+
+    /// Remote peer serving `metadata`, asking us for piece 0 once before answering.
+    async fn serve_metadata(mut remote: DuplexStream, metadata: Vec<u8>) {
+        let mut handshake = vec![0u8; 68];
+        remote.read_exact(&mut handshake).await.unwrap();
+        handshake[48..68].copy_from_slice(&[9; 20]);
+        remote.write_all(&handshake).await.unwrap();
+
+        let mut frame = Framed::new(remote, MessageCodec);
+        let mut ours = ExtendedHandshake::default();
+        ours.m
+            .insert("ut_metadata".to_string(), REMOTE_METADATA_ID as i64);
+        ours.metadata_size = Some(metadata.len());
+        frame
+            .send(PeerMessage::Extended {
+                ext_id: EXTENDED_HANDSHAKE_ID,
+                payload: ours.to_bytes().unwrap(),
+            })
+            .await
+            .unwrap();
+
+        let Some(Ok(PeerMessage::Extended { ext_id: 0, payload })) = frame.next().await else {
+            panic!("Expected an extended handshake");
+        };
+        let theirs = ExtendedHandshake::from_bytes(&payload).unwrap();
+        assert_eq!(theirs.extension_id("ut_metadata"), Some(UT_METADATA_ID));
+
+        let mut asked_back = false;
+        while let Some(Ok(message)) = frame.next().await {
+            let PeerMessage::Extended { ext_id, payload } = message else {
+                continue;
+            };
+            assert_eq!(ext_id, REMOTE_METADATA_ID);
+
+            let piece = match MetadataMessage::from_bytes(&payload).unwrap() {
+                MetadataMessage::Request { piece } => piece,
+                MetadataMessage::Reject { piece: 0 } => 0,
+                other => panic!("Unexpected {:?}", other),
+            };
+
+            if !asked_back {
+                asked_back = true;
+                let request = MetadataMessage::Request { piece: 0 };
+                frame
+                    .send(PeerMessage::Extended {
+                        ext_id: UT_METADATA_ID,
+                        payload: request.to_bytes().unwrap(),
+                    })
+                    .await
+                    .unwrap();
+                continue;
+            }
+
+            let start = piece * METADATA_PIECE_SIZE;
+            let end = (start + METADATA_PIECE_SIZE).min(metadata.len());
+            let data = MetadataMessage::Data {
+                piece,
+                total_size: metadata.len(),
+                data: metadata[start..end].to_vec(),
+            };
+            frame
+                .send(PeerMessage::Extended {
+                    ext_id: UT_METADATA_ID,
+                    payload: data.to_bytes().unwrap(),
+                })
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_metadata() -> anyhow::Result<()> {
+        let metadata = info_bytes();
+        assert!(metadata.len() > METADATA_PIECE_SIZE);
+        let magnet = Magnet {
+            info_hash: InfoHash::new(Sha1::digest(&metadata).into()),
+            display_name: None,
+            trackers: vec!["http://tracker.test/announce".to_string()],
+        };
+
+        let (local, remote) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(serve_metadata(remote, metadata));
+
+        let addr: SocketAddr = "127.0.0.1:6881".parse()?;
+        let mut peer = Peer::new(addr, magnet.info_hash, PEER_ID.parse().unwrap());
+        peer.connect_over(local, 0).await?;
+
+        let torrent = peer.fetch_metadata(&magnet).await?;
+        assert_eq!(torrent.info.name, "metadata");
+        assert_eq!(torrent.info.pieces.0.len(), 1000);
+        assert_eq!(torrent.info_hash, Some(magnet.info_hash));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_metadata_wrong_hash() -> anyhow::Result<()> {
+        let magnet = Magnet {
+            info_hash: InfoHash::new([2; 20]),
+            display_name: None,
+            trackers: vec![],
+        };
+
+        let (local, remote) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(serve_metadata(remote, info_bytes()));
+
+        let addr: SocketAddr = "127.0.0.1:6881".parse()?;
+        let mut peer = Peer::new(addr, magnet.info_hash, PEER_ID.parse().unwrap());
+        peer.connect_over(local, 0).await?;
+
+        assert!(peer.fetch_metadata(&magnet).await.is_err());
+        Ok(())
+    }
+}
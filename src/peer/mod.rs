@@ -1,49 +1,113 @@
 #![allow(dead_code)]
-use std::net::SocketAddrV4;
+use std::net::SocketAddr;
 
 mod address;
+mod choke;
 mod connect;
+mod extension;
 mod handshake;
+mod id;
+mod listen;
+mod metadata;
+mod mse;
 mod state;
+mod transfer;
+mod transport;
 
-use crate::message::{Bitfield, MessageCodec};
+pub use choke::{Choker, PeerRate, DEFAULT_UNCHOKE_SLOTS, RECHOKE_INTERVAL};
+pub use extension::PEX_INTERVAL;
+pub use handshake::DEFAULT_TIMEOUT;
+pub use id::PeerId;
+pub use mse::{EncryptionPolicy, MseStream};
+pub use transport::PeerTransport;
+
+pub(crate) use address::PeerList;
+
+use crate::message::{Bitfield, ExtendedHandshake, MessageCodec};
+use crate::torrent::InfoHash;
 use state::PeerState;
-use tokio::net::TcpStream;
+use tokio::time::{Duration, Instant};
 use tokio_util::codec::Framed;
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct PeerAddresses(pub Vec<SocketAddrV4>);
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerAddresses(pub Vec<SocketAddr>);
 
 // To make it more readable
 impl PeerAddresses {
-    pub fn iter(&self) -> std::slice::Iter<'_, SocketAddrV4> {
+    pub fn iter(&self) -> std::slice::Iter<'_, SocketAddr> {
         self.0.iter()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Splits the list into its IPv4 and IPv6 addresses, which use different compact forms.
+    pub fn split_by_family(self) -> (PeerAddresses, PeerAddresses) {
+        let (v4, v6) = self.0.into_iter().partition(SocketAddr::is_ipv4);
+        (PeerAddresses(v4), PeerAddresses(v6))
+    }
 }
 
 #[derive(Debug)]
 pub struct Peer {
-    addr: SocketAddrV4,
+    addr: SocketAddr,
+    timeout: Duration,
+    encryption: EncryptionPolicy,
     state: PeerState,
-    info_hash: [u8; 20],
-    peer_id: String,
+    info_hash: InfoHash,
+    peer_id: PeerId,
+    remote_peer_id: Option<[u8; 20]>,
+    remote_reserved: [u8; 8],
+    remote_extensions: Option<ExtendedHandshake>,
+    last_pex: Option<Instant>,
     bitfield: Option<Bitfield>,
-    tcp_stream: Option<Framed<TcpStream, MessageCodec>>,
+    total_pieces: usize,
+    stream: Option<Framed<Box<dyn PeerTransport>, MessageCodec>>,
 }
 
 impl Peer {
-    pub fn new(address: SocketAddrV4, info_hash: [u8; 20], peer_id: String) -> Self {
+    pub fn new(address: SocketAddr, info_hash: InfoHash, peer_id: PeerId) -> Self {
         Self {
             addr: address,
+            timeout: DEFAULT_TIMEOUT,
+            encryption: EncryptionPolicy::default(),
             state: PeerState::new(),
             info_hash,
             peer_id,
+            remote_peer_id: None,
+            remote_reserved: [0; 8],
+            remote_extensions: None,
+            last_pex: None,
             bitfield: None,
-            tcp_stream: None,
+            total_pieces: 0,
+            stream: None,
         }
     }
 
+    /// Overrides `DEFAULT_TIMEOUT` for connecting and handshaking, e.g. on high-latency networks.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Whether `connect` negotiates message stream encryption first, disabled by default.
+    pub fn with_encryption(mut self, policy: EncryptionPolicy) -> Self {
+        self.encryption = policy;
+        self
+    }
+
     pub fn bitfield(&self) -> Option<&Bitfield> {
         self.bitfield.as_ref()
     }
+
+    /// Whether the remote is choking us, true until it sends `Unchoke`.
+    pub fn is_choked(&self) -> bool {
+        self.state.is_choked()
+    }
+
+    /// The peer id the remote sent in its handshake response, `None` before the handshake.
+    pub fn remote_peer_id(&self) -> Option<&[u8; 20]> {
+        self.remote_peer_id.as_ref()
+    }
 }
@@ -0,0 +1,699 @@
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context as TaskContext, Poll};
+
+use anyhow::{bail, Context};
+use num_bigint::BigUint;
+use rand::Rng;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Duration};
+
+use super::{Peer, PeerTransport};
+use crate::torrent::InfoHash;
+
+// 768-bit safe prime and generator used by every MSE implementation
+const PRIME: &str = "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A63A36210000000000090563";
+const GENERATOR: u32 = 2;
+const KEY_LENGTH: usize = 96;
+
+// Verification constant, 8 zero bytes sent encrypted so the other side can find the stream
+const VC: [u8; 8] = [0; 8];
+const MAX_PADDING: usize = 512;
+
+// RC4 output discarded before use, the first bytes of the keystream are weak
+const RC4_DISCARD: usize = 1024;
+
+// Plaintext connections open with the protocol name length and the protocol name
+const HANDSHAKE_HEADER: &[u8; 20] = b"\x13BitTorrent protocol";
+const PEEK_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+const CRYPTO_PLAINTEXT: u32 = 0x01;
+const CRYPTO_RC4: u32 = 0x02;
+
+/// Whether connections negotiate message stream encryption (MSE/PE) before the BitTorrent
+/// handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncryptionPolicy {
+    /// Plain BitTorrent handshake, no key exchange.
+    #[default]
+    Disabled,
+    /// Try encryption, reconnect in plaintext if the peer doesn't support it.
+    Prefer,
+    /// Only talk to peers that encrypt the whole connection.
+    Require,
+}
+
+impl EncryptionPolicy {
+    fn crypto_provide(self) -> u32 {
+        match self {
+            EncryptionPolicy::Require => CRYPTO_RC4,
+            _ => CRYPTO_RC4 | CRYPTO_PLAINTEXT,
+        }
+    }
+
+    /// Picks one of the methods offered in `crypto_provide`, RC4 whenever possible.
+    fn select(self, crypto_provide: u32) -> Option<u32> {
+        if crypto_provide & CRYPTO_RC4 != 0 {
+            Some(CRYPTO_RC4)
+        } else if self != EncryptionPolicy::Require && crypto_provide & CRYPTO_PLAINTEXT != 0 {
+            Some(CRYPTO_PLAINTEXT)
+        } else {
+            None
+        }
+    }
+}
+
+struct Rc4 {
+    state: [u8; 256],
+    i: u8,
+    j: u8,
+}
+
+impl Rc4 {
+    fn new(key: &[u8]) -> Self {
+        let mut state = [0u8; 256];
+        for (i, byte) in state.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+
+        let mut j = 0u8;
+        for i in 0..256 {
+            j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+            state.swap(i, j as usize);
+        }
+
+        Self { state, i: 0, j: 0 }
+    }
+
+    /// Encrypts or decrypts `data` in place.
+    fn apply(&mut self, data: &mut [u8]) {
+        for byte in data {
+            self.i = self.i.wrapping_add(1);
+            self.j = self.j.wrapping_add(self.state[self.i as usize]);
+            self.state.swap(self.i as usize, self.j as usize);
+            let index = self.state[self.i as usize].wrapping_add(self.state[self.j as usize]);
+            *byte ^= self.state[index as usize];
+        }
+    }
+}
+
+impl fmt::Debug for Rc4 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rc4").finish_non_exhaustive()
+    }
+}
+
+struct KeyPair {
+    private: BigUint,
+    public: [u8; KEY_LENGTH],
+}
+
+impl KeyPair {
+    fn generate() -> Self {
+        let private = BigUint::from_bytes_be(&rand::random::<[u8; 20]>());
+        let public = BigUint::from(GENERATOR).modpow(&private, &prime());
+        Self {
+            private,
+            public: to_key_bytes(&public),
+        }
+    }
+
+    fn shared_secret(&self, remote_public: &[u8; KEY_LENGTH]) -> anyhow::Result<[u8; KEY_LENGTH]> {
+        let prime = prime();
+        let remote = BigUint::from_bytes_be(remote_public);
+        if remote <= BigUint::from(1u32) || remote >= &prime - 1u32 {
+            bail!("Peer sent an invalid public key");
+        }
+        Ok(to_key_bytes(&remote.modpow(&self.private, &prime)))
+    }
+}
+
+fn prime() -> BigUint {
+    BigUint::parse_bytes(PRIME.as_bytes(), 16).expect("MSE prime is valid hex")
+}
+
+// Keys are always sent as 96 big-endian bytes, left padded with zeros
+fn to_key_bytes(value: &BigUint) -> [u8; KEY_LENGTH] {
+    let bytes = value.to_bytes_be();
+    let mut key = [0u8; KEY_LENGTH];
+    key[KEY_LENGTH - bytes.len()..].copy_from_slice(&bytes);
+    key
+}
+
+fn hash(parts: &[&[u8]]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// RC4 keyed with `HASH(name, S, SKEY)`, "keyA" for data sent by the initiator and "keyB" for
+/// data sent by the receiver. SKEY is the info hash.
+fn cipher(name: &[u8], secret: &[u8], info_hash: &[u8; 20]) -> Rc4 {
+    let mut rc4 = Rc4::new(&hash(&[name, secret, info_hash]));
+    rc4.apply(&mut [0u8; RC4_DISCARD]);
+    rc4
+}
+
+// HASH('req2', SKEY) xor HASH('req3', S), lets the receiver find the torrent without revealing it
+fn obfuscated_info_hash(secret: &[u8], info_hash: &[u8; 20]) -> [u8; 20] {
+    let mut obfuscated = hash(&[b"req2", info_hash]);
+    for (byte, mask) in obfuscated.iter_mut().zip(hash(&[b"req3", secret])) {
+        *byte ^= mask;
+    }
+    obfuscated
+}
+
+fn padding() -> Vec<u8> {
+    let length = rand::thread_rng().gen_range(0..=MAX_PADDING);
+    (0..length).map(|_| rand::random()).collect()
+}
+
+/// Third step of the handshake: HASH('req1', S), the obfuscated info hash, then
+/// ENCRYPT(VC, crypto_provide, len(PadC), PadC, len(IA), IA) with empty PadC and IA.
+fn initiator_request(
+    secret: &[u8],
+    info_hash: &[u8; 20],
+    crypto_provide: u32,
+    outgoing: &mut Rc4,
+) -> Vec<u8> {
+    let mut request = hash(&[b"req1", secret]).to_vec();
+    request.extend_from_slice(&obfuscated_info_hash(secret, info_hash));
+
+    let mut encrypted = VC.to_vec();
+    encrypted.extend_from_slice(&crypto_provide.to_be_bytes());
+    encrypted.extend_from_slice(&0u16.to_be_bytes()); // PadC
+    encrypted.extend_from_slice(&0u16.to_be_bytes()); // IA
+    outgoing.apply(&mut encrypted);
+
+    request.extend_from_slice(&encrypted);
+    request
+}
+
+/// Reads until `pattern` shows up within `limit` bytes, skipping the random padding in front
+/// of it.
+async fn synchronize<S>(stream: &mut S, pattern: &[u8], limit: usize) -> anyhow::Result<()>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut window = Vec::with_capacity(limit);
+    while window.len() < limit {
+        window.push(stream.read_u8().await?);
+        if window.ends_with(pattern) {
+            return Ok(());
+        }
+    }
+    bail!("Pattern not found within {} bytes", limit)
+}
+
+async fn read_decrypted<S>(stream: &mut S, length: usize, incoming: &mut Rc4) -> io::Result<Vec<u8>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut data = vec![0u8; length];
+    stream.read_exact(&mut data).await?;
+    incoming.apply(&mut data);
+    Ok(data)
+}
+
+fn read_padding_length(bytes: &[u8]) -> anyhow::Result<usize> {
+    let length = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+    if length > MAX_PADDING {
+        bail!("Padding of {} bytes exceeds {}", length, MAX_PADDING);
+    }
+    Ok(length)
+}
+
+#[derive(Debug)]
+struct Ciphers {
+    incoming: Rc4,
+    outgoing: Rc4,
+}
+
+/// A stream after the MSE handshake, RC4 encrypted unless both sides settled on plaintext.
+#[derive(Debug)]
+pub struct MseStream<S> {
+    inner: S,
+    ciphers: Option<Ciphers>,
+    // Initial payload received during the handshake, already decrypted
+    buffered: Vec<u8>,
+    // Encrypted bytes accepted by `poll_write` but not yet written to `inner`
+    pending: Vec<u8>,
+}
+
+impl<S> MseStream<S> {
+    fn new(inner: S, ciphers: Option<Ciphers>, buffered: Vec<u8>) -> Self {
+        Self {
+            inner,
+            ciphers,
+            buffered,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.ciphers.is_some()
+    }
+}
+
+impl<S: AsyncWrite + Unpin> MseStream<S> {
+    fn poll_pending(&mut self, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        while !self.pending.is_empty() {
+            let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pending.drain(..written);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for MseStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.buffered.is_empty() {
+            let length = this.buffered.len().min(buf.remaining());
+            buf.put_slice(&this.buffered[..length]);
+            this.buffered.drain(..length);
+            return Poll::Ready(Ok(()));
+        }
+
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if let Some(ciphers) = this.ciphers.as_mut() {
+            ciphers.incoming.apply(&mut buf.filled_mut()[filled..]);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for MseStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.ciphers.is_none() {
+            return Pin::new(&mut this.inner).poll_write(cx, data);
+        }
+
+        // The keystream advances as we encrypt, so bytes are only accepted once the previous
+        // ones are fully written
+        ready!(this.poll_pending(cx))?;
+        this.pending.extend_from_slice(data);
+        if let Some(ciphers) = this.ciphers.as_mut() {
+            ciphers.outgoing.apply(&mut this.pending);
+        }
+        if let Poll::Ready(Err(e)) = this.poll_pending(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Runs the MSE handshake as the connecting side. The returned stream is ready for the
+/// BitTorrent handshake.
+pub async fn initiate<S>(
+    mut stream: S,
+    info_hash: &InfoHash,
+    policy: EncryptionPolicy,
+) -> anyhow::Result<MseStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let info_hash = info_hash.as_bytes();
+    let keys = KeyPair::generate();
+    stream
+        .write_all(&[&keys.public[..], &padding()].concat())
+        .await?;
+
+    let mut remote_public = [0u8; KEY_LENGTH];
+    stream
+        .read_exact(&mut remote_public)
+        .await
+        .context("Peer did not answer the key exchange")?;
+    let secret = keys.shared_secret(&remote_public)?;
+
+    let mut outgoing = cipher(b"keyA", &secret, info_hash);
+    let mut incoming = cipher(b"keyB", &secret, info_hash);
+    let request = initiator_request(&secret, info_hash, policy.crypto_provide(), &mut outgoing);
+    stream.write_all(&request).await?;
+
+    let mut encrypted_vc = VC;
+    incoming.apply(&mut encrypted_vc);
+    synchronize(&mut stream, &encrypted_vc, MAX_PADDING + VC.len())
+        .await
+        .context("Peer did not confirm the key exchange")?;
+
+    let header = read_decrypted(&mut stream, 6, &mut incoming).await?;
+    let crypto_select = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let pad_length = read_padding_length(&header[4..])?;
+    read_decrypted(&mut stream, pad_length, &mut incoming).await?;
+
+    if crypto_select & policy.crypto_provide() == 0 || crypto_select.count_ones() != 1 {
+        bail!("Peer selected crypto method {:#x}", crypto_select);
+    }
+
+    let ciphers = (crypto_select == CRYPTO_RC4).then_some(Ciphers { incoming, outgoing });
+    Ok(MseStream::new(stream, ciphers, Vec::new()))
+}
+
+/// Runs the MSE handshake as the accepting side for a connection to the torrent with
+/// `info_hash`.
+pub async fn respond<S>(
+    mut stream: S,
+    info_hash: &InfoHash,
+    policy: EncryptionPolicy,
+) -> anyhow::Result<MseStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let info_hash = info_hash.as_bytes();
+    let mut remote_public = [0u8; KEY_LENGTH];
+    stream.read_exact(&mut remote_public).await?;
+
+    let keys = KeyPair::generate();
+    stream
+        .write_all(&[&keys.public[..], &padding()].concat())
+        .await?;
+    let secret = keys.shared_secret(&remote_public)?;
+
+    synchronize(&mut stream, &hash(&[b"req1", &secret]), MAX_PADDING + 20)
+        .await
+        .context("Peer did not send its key exchange request")?;
+
+    let mut obfuscated = [0u8; 20];
+    stream.read_exact(&mut obfuscated).await?;
+    if obfuscated != obfuscated_info_hash(&secret, info_hash) {
+        bail!("Peer requested a different torrent");
+    }
+
+    let mut outgoing = cipher(b"keyB", &secret, info_hash);
+    let mut incoming = cipher(b"keyA", &secret, info_hash);
+
+    let header = read_decrypted(&mut stream, 14, &mut incoming).await?;
+    if header[..8] != VC {
+        bail!("Invalid verification constant");
+    }
+    let crypto_provide = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+    let pad_length = read_padding_length(&header[12..])?;
+    read_decrypted(&mut stream, pad_length, &mut incoming).await?;
+
+    let ia_length = read_decrypted(&mut stream, 2, &mut incoming).await?;
+    let ia_length = u16::from_be_bytes([ia_length[0], ia_length[1]]) as usize;
+    let initial_payload = read_decrypted(&mut stream, ia_length, &mut incoming).await?;
+
+    let crypto_select = policy
+        .select(crypto_provide)
+        .context("No crypto method in common with peer")?;
+
+    let mut reply = VC.to_vec();
+    reply.extend_from_slice(&crypto_select.to_be_bytes());
+    reply.extend_from_slice(&0u16.to_be_bytes()); // PadD
+    outgoing.apply(&mut reply);
+    stream.write_all(&reply).await?;
+
+    let ciphers = (crypto_select == CRYPTO_RC4).then_some(Ciphers { incoming, outgoing });
+    Ok(MseStream::new(stream, ciphers, initial_payload))
+}
+
+impl Peer {
+    /// Connects and runs the MSE handshake. Under `Prefer`, a peer that fails it is reconnected
+    /// in plaintext since it may simply not support encryption.
+    pub(super) async fn connect_encrypted(&self) -> anyhow::Result<Box<dyn PeerTransport>> {
+        let tcp_stream = self.connect_tcp().await?;
+        let result = match timeout(
+            self.timeout,
+            initiate(tcp_stream, &self.info_hash, self.encryption),
+        )
+        .await
+        {
+            Ok(result) => result,
+            Err(_) => Err(anyhow::anyhow!(
+                "Encryption handshake timed out after {:?}",
+                self.timeout
+            )),
+        };
+
+        match result {
+            Ok(stream) => Ok(Box::new(stream)),
+            Err(e) if self.encryption == EncryptionPolicy::Prefer => {
+                tracing::debug!(
+                    "Encryption with {} failed, falling back to plaintext: {:#}",
+                    self.addr,
+                    e
+                );
+                Ok(Box::new(self.connect_tcp().await?))
+            }
+            Err(e) => Err(e).context("Peer does not support encryption"),
+        }
+    }
+
+    /// Runs the MSE handshake on a connection the remote opened. Under `Prefer`, a connection
+    /// starting with the plaintext BitTorrent handshake is kept as is, under `Require` it is
+    /// rejected.
+    pub(super) async fn accept_encrypted(
+        &self,
+        tcp_stream: TcpStream,
+    ) -> anyhow::Result<Box<dyn PeerTransport>> {
+        let handshake = async {
+            if starts_with_handshake(&tcp_stream).await? {
+                if self.encryption == EncryptionPolicy::Require {
+                    bail!("Peer did not encrypt the connection");
+                }
+                return Ok(Box::new(tcp_stream) as Box<dyn PeerTransport>);
+            }
+
+            let stream = respond(tcp_stream, &self.info_hash, self.encryption).await?;
+            Ok(Box::new(stream) as Box<dyn PeerTransport>)
+        };
+
+        match timeout(self.timeout, handshake).await {
+            Ok(result) => result.context("Inbound encryption handshake failed"),
+            Err(_) => bail!("Encryption handshake timed out after {:?}", self.timeout),
+        }
+    }
+}
+
+/// Whether the first bytes received are the plaintext handshake header rather than an MSE
+/// public key. Nothing is consumed from the stream.
+async fn starts_with_handshake(tcp_stream: &TcpStream) -> anyhow::Result<bool> {
+    let mut header = [0u8; HANDSHAKE_HEADER.len()];
+    loop {
+        let length = tcp_stream.peek(&mut header).await?;
+        if length == 0 {
+            bail!("Peer closed the connection");
+        }
+        if header[..length] != HANDSHAKE_HEADER[..length] {
+            return Ok(false);
+        }
+        if length == HANDSHAKE_HEADER.len() {
+            return Ok(true);
+        }
+        // Peeking returns the same partial header until more bytes arrive
+        sleep(PEEK_RETRY_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::{MessageCodec, PeerMessage};
+    use futures::SinkExt;
+    use tokio::net::TcpListener;
+    use tokio_util::codec::Framed;
+
+    const INFO_HASH: InfoHash = InfoHash::new([4; 20]);
+
+    #[test]
+    fn test_rc4_vectors() {
+        for (key, plaintext, ciphertext) in [
+            ("Key", "Plaintext", "bbf316e8d940af0ad3"),
+            ("Wiki", "pedia", "1021bf0420"),
+            ("Secret", "Attack at dawn", "45a01f645fc35b383552544b9bf5"),
+        ] {
+            let mut data = plaintext.as_bytes().to_vec();
+            Rc4::new(key.as_bytes()).apply(&mut data);
+            assert_eq!(hex::encode(&data), ciphertext);
+        }
+    }
+
+    #[test]
+    fn test_key_exchange() -> anyhow::Result<()> {
+        assert_eq!(prime().to_bytes_be().len(), KEY_LENGTH);
+
+        let initiator = KeyPair::generate();
+        let receiver = KeyPair::generate();
+        assert_eq!(
+            initiator.shared_secret(&receiver.public)?,
+            receiver.shared_secret(&initiator.public)?
+        );
+
+        assert!(initiator.shared_secret(&[0; KEY_LENGTH]).is_err());
+        assert!(initiator
+            .shared_secret(&to_key_bytes(&BigUint::from(1u32)))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_derivation() {
+        let secret = [7u8; KEY_LENGTH];
+
+        // First keystream bytes after the 1024 discarded ones, computed with OpenSSL's RC4 over
+        // SHA1('keyA' + S + SKEY) and SHA1('keyB' + S + SKEY)
+        let key_a = [
+            0x38, 0x86, 0x60, 0x1a, 0x60, 0xfc, 0x2f, 0xdf, 0x15, 0x68, 0x77, 0x65, 0xa2, 0x56,
+            0x87, 0x3f,
+        ];
+        let key_b = [
+            0x47, 0x8a, 0x3f, 0xa1, 0x6f, 0x6d, 0x86, 0xaf, 0x6c, 0x69, 0xb7, 0x66, 0xe6, 0xcc,
+            0xae, 0xfe,
+        ];
+
+        let mut block = [0u8; 16];
+        cipher(b"keyA", &secret, INFO_HASH.as_bytes()).apply(&mut block);
+        assert_eq!(block, key_a);
+
+        let mut block = [0u8; 16];
+        cipher(b"keyB", &secret, INFO_HASH.as_bytes()).apply(&mut block);
+        assert_eq!(block, key_b);
+    }
+
+    #[test]
+    fn test_initiator_request_layout() {
+        let secret = [7u8; KEY_LENGTH];
+        let mut outgoing = cipher(b"keyA", &secret, INFO_HASH.as_bytes());
+        let request = initiator_request(&secret, INFO_HASH.as_bytes(), CRYPTO_RC4, &mut outgoing);
+
+        assert_eq!(request.len(), 20 + 20 + 8 + 4 + 2 + 2);
+        assert_eq!(request[..20], hash(&[b"req1", &secret]));
+
+        let req2 = hash(&[b"req2", INFO_HASH.as_bytes()]);
+        let req3 = hash(&[b"req3", &secret]);
+        let obfuscated: Vec<u8> = req2.iter().zip(req3).map(|(a, b)| a ^ b).collect();
+        assert_eq!(request[20..40], obfuscated);
+
+        let mut encrypted = request[40..].to_vec();
+        cipher(b"keyA", &secret, INFO_HASH.as_bytes()).apply(&mut encrypted);
+        assert_eq!(encrypted, [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_select() {
+        let both = CRYPTO_RC4 | CRYPTO_PLAINTEXT;
+        assert_eq!(EncryptionPolicy::Prefer.select(both), Some(CRYPTO_RC4));
+        assert_eq!(
+            EncryptionPolicy::Prefer.select(CRYPTO_PLAINTEXT),
+            Some(CRYPTO_PLAINTEXT)
+        );
+        assert_eq!(EncryptionPolicy::Require.select(CRYPTO_PLAINTEXT), None);
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_exchange() -> anyhow::Result<()> {
+        let (local, remote) = tokio::io::duplex(4096);
+
+        let receiver = tokio::spawn(async move {
+            let mut stream = respond(remote, &INFO_HASH, EncryptionPolicy::Require)
+                .await
+                .unwrap();
+            let mut message = [0u8; 5];
+            stream.read_exact(&mut message).await.unwrap();
+            stream.write_all(b"world").await.unwrap();
+            message
+        });
+
+        let mut stream = initiate(local, &INFO_HASH, EncryptionPolicy::Prefer).await?;
+        assert!(stream.is_encrypted());
+        stream.write_all(b"hello").await?;
+
+        let mut reply = [0u8; 5];
+        stream.read_exact(&mut reply).await?;
+        assert_eq!(&reply, b"world");
+        assert_eq!(&receiver.await?, b"hello");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wrong_info_hash() {
+        let (local, remote) = tokio::io::duplex(4096);
+        let receiver = tokio::spawn(async move {
+            respond(remote, &InfoHash::new([5; 20]), EncryptionPolicy::Prefer).await
+        });
+
+        assert!(initiate(local, &INFO_HASH, EncryptionPolicy::Prefer)
+            .await
+            .is_err());
+        assert!(receiver.await.unwrap().is_err());
+    }
+
+    // A peer without MSE support, it drops the first connection since the key exchange isn't
+    // a valid handshake, then answers a plaintext one
+    async fn plaintext_peer() -> anyhow::Result<std::net::SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut handshake = vec![0u8; 68];
+                stream.read_exact(&mut handshake).await.unwrap();
+                if handshake[1..20] != *b"BitTorrent protocol" {
+                    continue;
+                }
+
+                handshake[48..68].copy_from_slice(&[9; 20]);
+                stream.write_all(&handshake).await.unwrap();
+                let mut frame = Framed::new(stream, MessageCodec);
+                frame.send(PeerMessage::HaveNone).await.unwrap();
+                frame.send(PeerMessage::Unchoke).await.unwrap();
+            }
+        });
+
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn test_prefer_falls_back_to_plaintext() -> anyhow::Result<()> {
+        let addr = plaintext_peer().await?;
+        let mut peer = Peer::new(addr, INFO_HASH, "-TR0001-mse-fallback".parse().unwrap())
+            .with_encryption(EncryptionPolicy::Prefer);
+
+        peer.connect(8).await?;
+        assert_eq!(peer.remote_peer_id(), Some(&[9; 20]));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_require_rejects_plaintext_peer() -> anyhow::Result<()> {
+        let addr = plaintext_peer().await?;
+        let mut peer = Peer::new(addr, INFO_HASH, "-TR0001-mse-required".parse().unwrap())
+            .with_encryption(EncryptionPolicy::Require);
+
+        assert!(peer.connect(8).await.is_err());
+        Ok(())
+    }
+}
@@ -18,4 +18,8 @@ impl PeerState {
     pub fn unchoke(&mut self) {
         self.choked = false;
     }
+
+    pub fn is_choked(&self) -> bool {
+        self.choked
+    }
 }
@@ -0,0 +1,210 @@
+use anyhow::{bail, Context};
+use futures::StreamExt;
+use tokio::time::timeout;
+use tracing::instrument;
+
+use super::Peer;
+use crate::message::{Bitfield, PeerMessage};
+
+impl Peer {
+    /// Receives the next message, keeping the choke state and bitfield up to date.
+    pub async fn receive_message(&mut self) -> anyhow::Result<PeerMessage> {
+        let frame = self.stream.as_mut().context("Peer is not connected")?;
+        let message = timeout(self.timeout, frame.next())
+            .await
+            .context(format!("No message from peer within {:?}", self.timeout))?
+            .context("Peer closed the connection")?
+            .context("Failed to receive message")?;
+
+        match &message {
+            PeerMessage::Choke => self.state.choke(),
+            PeerMessage::Unchoke => self.state.unchoke(),
+            PeerMessage::Have(index) => {
+                if let Some(bitfield) = self.bitfield.as_mut() {
+                    bitfield.set_piece(*index as usize);
+                }
+            }
+            // Peers may send these after their first message, e.g. following an extended
+            // handshake
+            PeerMessage::Bitfield(data) => self.bitfield = Some(Bitfield::from_bytes(data.clone())),
+            PeerMessage::HaveAll | PeerMessage::HaveNone if !self.supports_fast_extension() => {
+                bail!("Peer sent a fast extension message without negotiating it");
+            }
+            PeerMessage::HaveAll => self.bitfield = Some(Bitfield::full(self.total_pieces)),
+            PeerMessage::HaveNone => self.bitfield = Some(Bitfield::new(self.total_pieces)),
+            _ => {}
+        }
+
+        Ok(message)
+    }
+
+    pub async fn send_interested(&mut self) -> anyhow::Result<()> {
+        self.send_message(PeerMessage::Interested).await
+    }
+
+    /// Waits until the peer unchokes us, dropping any other message.
+    pub async fn wait_for_unchoke(&mut self) -> anyhow::Result<()> {
+        while self.is_choked() {
+            self.receive_message().await?;
+        }
+        Ok(())
+    }
+
+    /// Requests a block and waits for it. Fails if the peer chokes us or rejects the request
+    /// in the meantime.
+    #[instrument(skip(self, index, begin), fields(peer = %self.addr, piece = index, offset = begin))]
+    pub async fn request_block(
+        &mut self,
+        index: u32,
+        begin: u32,
+        length: u32,
+    ) -> anyhow::Result<Vec<u8>> {
+        self.send_message(PeerMessage::Request {
+            index,
+            begin,
+            length,
+        })
+        .await?;
+
+        loop {
+            match self.receive_message().await? {
+                PeerMessage::Piece {
+                    index: piece_index,
+                    begin: piece_begin,
+                    block,
+                } if piece_index == index && piece_begin == begin => {
+                    if block.len() != length as usize {
+                        bail!("Expected {} bytes, peer sent {}", length, block.len());
+                    }
+                    tracing::debug!(piece = index, offset = begin, length, "Received block");
+                    return Ok(block);
+                }
+                PeerMessage::Choke => bail!("Peer choked us before sending the block"),
+                PeerMessage::RejectRequest {
+                    index: rejected_index,
+                    begin: rejected_begin,
+                    ..
+                } if rejected_index == index && rejected_begin == begin => {
+                    bail!("Peer rejected the request")
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::MessageCodec;
+    use crate::torrent::InfoHash;
+    use futures::SinkExt;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_util::codec::Framed;
+    use tracing_test::traced_test;
+
+    const INFO_HASH: InfoHash = InfoHash::new([6; 20]);
+    const PEER_ID: &str = "-TR0001-transfertest";
+
+    #[tokio::test]
+    async fn test_late_bitfield_messages() -> anyhow::Result<()> {
+        let (local, mut remote) = tokio::io::duplex(64 * 1024);
+
+        tokio::spawn(async move {
+            let mut handshake = vec![0u8; 68];
+            remote.read_exact(&mut handshake).await.unwrap();
+            handshake[48..68].copy_from_slice(&[9; 20]);
+            remote.write_all(&handshake).await.unwrap();
+
+            let mut frame = Framed::new(remote, MessageCodec);
+            for message in [
+                PeerMessage::Unchoke,
+                PeerMessage::Bitfield(vec![0b0100_0000, 0]),
+                PeerMessage::HaveAll,
+                PeerMessage::HaveNone,
+            ] {
+                frame.send(message).await.unwrap();
+            }
+        });
+
+        let addr: SocketAddr = "127.0.0.1:6881".parse()?;
+        let mut peer = Peer::new(addr, INFO_HASH, PEER_ID.parse().unwrap());
+        assert_eq!(peer.connect_over(local, 10).await?.count_set(), 0);
+
+        peer.receive_message().await?;
+        assert!(peer.bitfield().unwrap().has_piece(1));
+        assert_eq!(peer.bitfield().unwrap().count_set(), 1);
+
+        peer.receive_message().await?;
+        assert_eq!(peer.bitfield().unwrap().count_set(), 10);
+
+        peer.receive_message().await?;
+        assert_eq!(peer.bitfield().unwrap().count_set(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_block_exchange_in_memory() -> anyhow::Result<()> {
+        let (local, mut remote) = tokio::io::duplex(64 * 1024);
+
+        let remote_task = tokio::spawn(async move {
+            let mut handshake = vec![0u8; 68];
+            remote.read_exact(&mut handshake).await.unwrap();
+            handshake[48..68].copy_from_slice(&[9; 20]);
+            remote.write_all(&handshake).await.unwrap();
+
+            let mut frame = Framed::new(remote, MessageCodec);
+            frame
+                .send(PeerMessage::Bitfield(vec![0b1000_0000]))
+                .await
+                .unwrap();
+            assert_eq!(
+                frame.next().await.unwrap().unwrap(),
+                PeerMessage::Interested
+            );
+            frame.send(PeerMessage::Unchoke).await.unwrap();
+
+            match frame.next().await.unwrap().unwrap() {
+                PeerMessage::Request {
+                    index,
+                    begin,
+                    length,
+                } => {
+                    frame
+                        .send(PeerMessage::Piece {
+                            index,
+                            begin,
+                            block: vec![7; length as usize],
+                        })
+                        .await
+                        .unwrap();
+                }
+                other => panic!("Expected a request, got {:?}", other),
+            }
+        });
+
+        let addr: SocketAddr = "127.0.0.1:6881".parse()?;
+        let mut peer = Peer::new(addr, INFO_HASH, PEER_ID.parse().unwrap());
+
+        let bitfield = peer.connect_over(local, 8).await?;
+        assert!(bitfield.has_piece(0));
+
+        peer.send_interested().await?;
+        peer.wait_for_unchoke().await?;
+        let block = peer.request_block(0, 0, 16).await?;
+        assert_eq!(block, vec![7; 16]);
+        logs_assert(|lines| {
+            lines
+                .iter()
+                .find(|line| line.contains("Received block"))
+                .filter(|line| line.contains("piece=0") && line.contains("length=16"))
+                .map(|_| ())
+                .ok_or_else(|| "No block event with a piece field".to_string())
+        });
+
+        remote_task.await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,9 @@
+use std::fmt;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A byte stream peers can talk over, TCP in practice. Anything else, like an in-memory
+/// `tokio::io::duplex` in tests or an encrypted stream, works through `Peer::connect_over`.
+pub trait PeerTransport: AsyncRead + AsyncWrite + Unpin + Send + Sync + fmt::Debug {}
+
+impl<T> PeerTransport for T where T: AsyncRead + AsyncWrite + Unpin + Send + Sync + fmt::Debug {}
@@ -0,0 +1,3 @@
+pub mod verify;
+
+pub use verify::{verify_all, verify_piece, VerifyReport};
@@ -0,0 +1,124 @@
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use sha1::{Digest, Sha1};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::torrent::Torrent;
+
+/// Checks downloaded piece data against its SHA1 hash from the torrent's `pieces`.
+pub fn verify_piece(data: &[u8], expected_hash: &[u8; 20]) -> bool {
+    let hash: [u8; 20] = Sha1::digest(data).into();
+    &hash == expected_hash
+}
+
+/// Piece indices of a download, grouped by verification outcome.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VerifyReport {
+    pub good: Vec<usize>,
+    pub bad: Vec<usize>,
+    /// Pieces with at least one byte in a missing or too short file.
+    pub missing: Vec<usize>,
+}
+
+impl VerifyReport {
+    pub fn is_complete(&self) -> bool {
+        self.bad.is_empty() && self.missing.is_empty()
+    }
+}
+
+/// Checks every piece of the content saved under `data_dir` against the torrent's hashes.
+#[tracing::instrument(skip(torrent), fields(info_hash = %torrent.info_hash.map(|info_hash| info_hash.to_hex()).unwrap_or_default()))]
+pub async fn verify_all(torrent: &Torrent, data_dir: &Path) -> anyhow::Result<VerifyReport> {
+    let files = content_files(torrent, data_dir);
+    let mut report = VerifyReport::default();
+
+    for (index, hash) in torrent.info.pieces.0.iter().enumerate() {
+        let start = index * torrent.info.piece_length;
+        let length = torrent.piece_len(index) as usize;
+
+        match read_span(&files, start, length).await? {
+            Some(data) if verify_piece(&data, hash) => report.good.push(index),
+            Some(_) => {
+                tracing::debug!(piece = index, "Hash mismatch");
+                report.bad.push(index);
+            }
+            None => report.missing.push(index),
+        }
+    }
+
+    tracing::info!(
+        name = %torrent.info.name,
+        good = report.good.len(),
+        bad = report.bad.len(),
+        missing = report.missing.len(),
+        "Verified content"
+    );
+    Ok(report)
+}
+
+/// Where each file of the torrent is saved under `data_dir`, with its length, in content order.
+fn content_files(torrent: &Torrent, data_dir: &Path) -> Vec<(PathBuf, usize)> {
+    torrent
+        .files()
+        .into_iter()
+        .map(|file| (data_dir.join(file.path), file.length))
+        .collect()
+}
+
+/// Reads `length` bytes at `start` of the concatenated files. `None` if part of the span is in
+/// a file that doesn't exist or is too short.
+async fn read_span(
+    files: &[(PathBuf, usize)],
+    start: usize,
+    length: usize,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let end = start + length;
+    let mut data = Vec::with_capacity(length);
+    let mut file_start = 0;
+
+    for (path, file_length) in files {
+        let file_end = file_start + file_length;
+        if file_end > start && file_start < end {
+            let from = start.max(file_start) - file_start;
+            let to = end.min(file_end) - file_start;
+
+            let mut file = match File::open(path).await {
+                Ok(file) => file,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e).context(format!("Failed to open {}", path.display())),
+            };
+            file.seek(SeekFrom::Start(from as u64)).await?;
+
+            let mut buffer = vec![0u8; to - from];
+            match file.read_exact(&mut buffer).await {
+                Ok(_) => data.extend_from_slice(&buffer),
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e).context(format!("Failed to read {}", path.display())),
+            }
+        }
+        file_start = file_end;
+    }
+
+    Ok(Some(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_piece() {
+        // SHA1 of "abc"
+        let hash: [u8; 20] = hex::decode("a9993e364706816aba3e25717850c26c9cd0d89d")
+            .unwrap()
+            .try_into()
+            .unwrap();
+
+        assert!(verify_piece(b"abc", &hash));
+        assert!(!verify_piece(b"abd", &hash));
+        assert!(!verify_piece(b"", &hash));
+    }
+}
@@ -0,0 +1,150 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use sha1::{Digest, Sha1};
+use tokio::io::AsyncReadExt;
+
+use super::{File, Hashes, Info, Keys, Torrent};
+
+impl Torrent {
+    /// Builds a torrent for the file or directory at `path`. Directories become multi-file
+    /// torrents with their files in sorted path order, leaving out empty files. Content is read
+    /// one piece at a time, so large files aren't loaded into memory.
+    #[tracing::instrument]
+    pub async fn create(
+        path: impl AsRef<Path> + std::fmt::Debug,
+        piece_length: usize,
+        announce: &str,
+    ) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        if piece_length == 0 {
+            bail!("Piece length must be positive");
+        }
+
+        let name = path
+            .file_name()
+            .context("Path has no file name")?
+            .to_string_lossy()
+            .into_owned();
+
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .context("Failed to read path metadata")?;
+        let files = if metadata.is_dir() {
+            list_files(path).await?
+        } else {
+            vec![(path.to_path_buf(), Vec::new())]
+        };
+        if files.is_empty() {
+            bail!("Directory {} has no files", path.display());
+        }
+
+        let mut pieces = Vec::new();
+        let mut piece = Vec::with_capacity(piece_length);
+        let mut torrent_files = Vec::with_capacity(files.len());
+
+        // Pieces span file boundaries, the content is hashed as if the files were concatenated
+        for (file_path, components) in files {
+            let mut file = tokio::fs::File::open(&file_path)
+                .await
+                .with_context(|| format!("Failed to open {}", file_path.display()))?;
+
+            let mut length = 0;
+            loop {
+                // Fill the rest of the current piece, which may have started in the previous file
+                let filled = piece.len();
+                piece.resize(piece_length, 0);
+                let read = file
+                    .read(&mut piece[filled..])
+                    .await
+                    .with_context(|| format!("Failed to read {}", file_path.display()))?;
+                piece.truncate(filled + read);
+                if read == 0 {
+                    break;
+                }
+                length += read;
+
+                if piece.len() == piece_length {
+                    pieces.push(Sha1::digest(&piece).into());
+                    piece.clear();
+                }
+            }
+
+            torrent_files.push(File {
+                length,
+                path: components,
+            });
+        }
+
+        // Final short piece
+        if !piece.is_empty() {
+            pieces.push(Sha1::digest(&piece).into());
+        }
+
+        let keys = if metadata.is_dir() {
+            Keys::MultiFile {
+                files: torrent_files,
+            }
+        } else {
+            Keys::SingleFile {
+                length: torrent_files[0].length,
+            }
+        };
+
+        let mut torrent = Torrent {
+            announce: announce.to_string(),
+            announce_list: None,
+            creation_date: None,
+            comment: None,
+            created_by: None,
+            info: Info {
+                name,
+                piece_length,
+                pieces: Hashes(pieces),
+                private: None,
+                keys,
+                extra: Default::default(),
+            },
+            info_hash: None,
+        };
+        torrent.validate().context("Created an invalid torrent")?;
+        torrent.get_info_hash()?;
+
+        tracing::info!("Created torrent for {}", torrent.info.name);
+        Ok(torrent)
+    }
+}
+
+/// Every non-empty file under `root` with its path components relative to `root`, sorted by
+/// those components. Empty files are skipped as torrents can't list zero-length files.
+async fn list_files(root: &Path) -> anyhow::Result<Vec<(PathBuf, Vec<String>)>> {
+    let mut files = Vec::new();
+    let mut directories = vec![root.to_path_buf()];
+
+    while let Some(directory) = directories.pop() {
+        let mut entries = tokio::fs::read_dir(&directory)
+            .await
+            .with_context(|| format!("Failed to read directory {}", directory.display()))?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                directories.push(path);
+                continue;
+            }
+            if entry.metadata().await?.len() == 0 {
+                continue;
+            }
+
+            let components: Vec<String> = path
+                .strip_prefix(root)?
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            files.push((path, components));
+        }
+    }
+
+    files.sort_by(|(_, a), (_, b)| a.cmp(b));
+    Ok(files)
+}
@@ -0,0 +1,8 @@
+/// Torrent files that parse as bencode but can't be used.
+#[derive(Debug, thiserror::Error)]
+pub enum TorrentError {
+    /// BitTorrent v2 torrents (BEP 52) without the v1 keys of a hybrid torrent. Their info hash
+    /// is SHA-256 based and they have no `pieces`, so the v1 model can't represent them.
+    #[error("BitTorrent v2 torrents are not supported, only v1 and hybrid torrents are")]
+    UnsupportedV2,
+}
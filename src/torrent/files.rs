@@ -0,0 +1,185 @@
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+use super::{Keys, Torrent};
+use crate::message::Bitfield;
+
+/// A file of the torrent's content and where it sits in the concatenation of all files that
+/// pieces are cut from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileEntry {
+    /// Path relative to the download directory, starting with the torrent's name.
+    pub path: PathBuf,
+    pub length: usize,
+    /// Offset of the file's first byte in the concatenated content.
+    pub offset: usize,
+}
+
+impl FileEntry {
+    /// Offset just past the file's last byte.
+    pub fn end(&self) -> usize {
+        self.offset + self.length
+    }
+}
+
+/// How much of a file is covered by completed pieces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileProgress {
+    pub path: PathBuf,
+    pub completed_bytes: usize,
+    pub total_bytes: usize,
+}
+
+impl FileProgress {
+    pub fn is_complete(&self) -> bool {
+        self.completed_bytes == self.total_bytes
+    }
+}
+
+impl Torrent {
+    pub fn total_files(&self) -> usize {
+        match &self.info.keys {
+            Keys::SingleFile { .. } => 1,
+            Keys::MultiFile { files } => files.len(),
+        }
+    }
+
+    /// Every file in content order, with its offset.
+    pub fn files(&self) -> Vec<FileEntry> {
+        let root = PathBuf::from(&self.info.name);
+        match &self.info.keys {
+            Keys::SingleFile { length } => vec![FileEntry {
+                path: root,
+                length: *length,
+                offset: 0,
+            }],
+            Keys::MultiFile { files } => {
+                let mut offset = 0;
+                files
+                    .iter()
+                    .map(|file| {
+                        let entry = FileEntry {
+                            path: root.join(file.path.iter().collect::<PathBuf>()),
+                            length: file.length,
+                            offset,
+                        };
+                        offset += file.length;
+                        entry
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Indices of the pieces holding some of the file at `index`, `None` if there is no such
+    /// file. The first and last may be shared with neighbouring files.
+    pub fn pieces_for_file(&self, index: usize) -> Option<RangeInclusive<usize>> {
+        let file = self.files().into_iter().nth(index)?;
+        let piece_length = self.info.piece_length;
+        let last_byte = file.end().saturating_sub(1).max(file.offset);
+        Some(file.offset / piece_length..=last_byte / piece_length)
+    }
+
+    /// Progress of every file given the `completed` pieces. Pieces shared with neighbouring
+    /// files only count for the bytes inside the file.
+    pub fn file_progress(&self, completed: &Bitfield) -> Vec<FileProgress> {
+        let piece_length = self.info.piece_length;
+        self.files()
+            .into_iter()
+            .map(|file| {
+                let first_piece = file.offset / piece_length;
+                let completed_bytes = (first_piece..)
+                    .take_while(|piece| piece * piece_length < file.end())
+                    .filter(|piece| completed.has_piece(*piece))
+                    .map(|piece| {
+                        let start = (piece * piece_length).max(file.offset);
+                        let end = ((piece + 1) * piece_length).min(file.end());
+                        end - start
+                    })
+                    .sum();
+
+                FileProgress {
+                    path: file.path,
+                    completed_bytes,
+                    total_bytes: file.length,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::File;
+
+    fn multi_file_torrent(lengths: &[usize], piece_length: usize) -> Torrent {
+        let files: Vec<File> = lengths
+            .iter()
+            .enumerate()
+            .map(|(i, length)| File {
+                length: *length,
+                path: vec!["dir".to_string(), format!("{}.bin", i)],
+            })
+            .collect();
+        let total: usize = lengths.iter().sum();
+
+        let mut torrent =
+            Torrent::test_single_file("http://tracker.test/announce", total, piece_length);
+        torrent.info.name = "content".to_string();
+        torrent.info.keys = Keys::MultiFile { files };
+        torrent
+    }
+
+    #[test]
+    fn test_files_offsets() {
+        let torrent = multi_file_torrent(&[10, 25, 5], 16);
+        let files = torrent.files();
+
+        assert_eq!(torrent.total_files(), 3);
+        assert_eq!(
+            files.iter().map(|file| file.offset).collect::<Vec<_>>(),
+            vec![0, 10, 35]
+        );
+        assert_eq!(files[1].path, PathBuf::from("content/dir/1.bin"));
+        assert_eq!(files.last().unwrap().end(), torrent.length());
+    }
+
+    #[test]
+    fn test_pieces_for_file() {
+        // Pieces cover bytes 0..16, 16..32 and 32..40
+        let torrent = multi_file_torrent(&[10, 25, 5], 16);
+
+        assert_eq!(torrent.pieces_for_file(0), Some(0..=0));
+        assert_eq!(torrent.pieces_for_file(1), Some(0..=2));
+        assert_eq!(torrent.pieces_for_file(2), Some(2..=2));
+        assert_eq!(torrent.pieces_for_file(3), None);
+    }
+
+    #[test]
+    fn test_file_progress() {
+        // Pieces cover bytes 0..16, 16..32 and 32..40, the second is shared by both files
+        let torrent = multi_file_torrent(&[20, 20], 16);
+        let mut completed = Bitfield::new(3);
+        completed.set_piece(0);
+        completed.set_piece(1);
+
+        let progress = torrent.file_progress(&completed);
+        assert!(progress[0].is_complete());
+        assert_eq!(progress[0].completed_bytes, 20);
+        assert!(!progress[1].is_complete());
+        assert_eq!(progress[1].completed_bytes, 12);
+        assert_eq!(progress[1].total_bytes, 20);
+
+        let progress = torrent.file_progress(&Bitfield::new(3));
+        assert!(progress.iter().all(|file| file.completed_bytes == 0));
+    }
+
+    #[test]
+    fn test_pieces_for_file_on_boundary() {
+        let torrent = multi_file_torrent(&[16, 16], 16);
+
+        assert_eq!(torrent.pieces_for_file(0), Some(0..=0));
+        assert_eq!(torrent.pieces_for_file(1), Some(1..=1));
+    }
+}
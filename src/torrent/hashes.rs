@@ -17,7 +17,7 @@ impl<'de> Visitor<'de> for HashesVisitor {
     where
         E: de::Error,
     {
-        if v.len() % 20 != 0 {
+        if !v.len().is_multiple_of(20) {
             return Err(E::custom(format!("length is {}", v.len())));
         }
         // TODO: use array_chunks when stable
@@ -47,3 +47,22 @@ impl Serialize for Hashes {
         serializer.serialize_bytes(&single_slice)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_as_single_byte_string() {
+        let hashes = Hashes(vec![[1u8; 20], [2u8; 20]]);
+        let bytes = serde_bencode::to_bytes(&hashes).unwrap();
+
+        let mut expected = b"40:".to_vec();
+        expected.extend_from_slice(&[1u8; 20]);
+        expected.extend_from_slice(&[2u8; 20]);
+        assert_eq!(bytes, expected);
+
+        let decoded: Hashes = serde_bencode::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.0, hashes.0);
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Context;
+
+/// SHA1 of a torrent's bencoded info dictionary, identifying it to trackers and peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InfoHash([u8; 20]);
+
+impl InfoHash {
+    pub const fn new(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 20] {
+        &self.0
+    }
+
+    /// Lowercase hex, as shown to users and used in magnet links.
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    /// Every byte percent-encoded, for the `info_hash` query parameter of tracker requests.
+    pub fn url_encode(&self) -> String {
+        let mut encoded = String::with_capacity(self.0.len() * 3);
+        for byte in self.0 {
+            encoded.push('%');
+            encoded.push_str(&format!("{:02X}", byte));
+        }
+        encoded
+    }
+}
+
+impl FromStr for InfoHash {
+    type Err = anyhow::Error;
+
+    /// Parses 40 hex characters.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s).context("Invalid hex info hash")?;
+        let bytes: [u8; 20] = bytes
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("Info hash is not 20 bytes"))?;
+        Ok(Self(bytes))
+    }
+}
+
+impl From<[u8; 20]> for InfoHash {
+    fn from(bytes: [u8; 20]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl fmt::Display for InfoHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_round_trip() {
+        let hex = "6d4795dee70aeb88e03e5336ca7c9fcf0a1e206d";
+        let info_hash: InfoHash = hex.parse().unwrap();
+        assert_eq!(info_hash.to_hex(), hex);
+        assert_eq!(info_hash.to_string(), hex);
+
+        assert!("6d4795".parse::<InfoHash>().is_err());
+        assert!("zz4795dee70aeb88e03e5336ca7c9fcf0a1e206d"
+            .parse::<InfoHash>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_url_encode() {
+        let info_hash = InfoHash::new([0xAB; 20]);
+        assert_eq!(info_hash.url_encode(), "%AB".repeat(20));
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::{bail, Context};
+use sha1::{Digest, Sha1};
+use std::str::FromStr;
+
+use super::{Info, InfoHash, Torrent};
+
+/// What a magnet link (BEP 9) tells us about a torrent before its info dictionary is fetched
+/// from peers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Magnet {
+    pub info_hash: InfoHash,
+
+    /// Suggested name, from the `dn` parameter.
+    pub display_name: Option<String>,
+
+    /// Tracker URLs, from the `tr` parameters, in the order given.
+    pub trackers: Vec<String>,
+}
+
+impl Magnet {
+    /// Parses `magnet:?xt=urn:btih:<hash>&dn=<name>&tr=<tracker>`, where the hash is either 40
+    /// hex characters or 32 base32 characters.
+    pub fn parse(uri: &str) -> anyhow::Result<Self> {
+        let url = reqwest::Url::parse(uri).context("Invalid magnet URI")?;
+        if url.scheme() != "magnet" {
+            bail!("Not a magnet URI: {}", uri);
+        }
+
+        let mut info_hash = None;
+        let mut display_name = None;
+        let mut trackers = Vec::new();
+
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "xt" => {
+                    // Other exact topics (e.g. v2 btmh) are ignored
+                    if let Some(hash) = value.strip_prefix("urn:btih:") {
+                        info_hash = Some(parse_info_hash(hash)?);
+                    }
+                }
+                "dn" => display_name = Some(value.into_owned()),
+                "tr" => trackers.push(value.into_owned()),
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            info_hash: info_hash.context("Magnet URI has no urn:btih exact topic")?,
+            display_name,
+            trackers,
+        })
+    }
+}
+
+impl Torrent {
+    /// Builds the torrent a magnet link refers to from its info dictionary, as fetched from
+    /// peers. Fails if the dictionary doesn't hash to the magnet's info hash.
+    pub fn from_metadata(magnet: &Magnet, info: &[u8]) -> anyhow::Result<Self> {
+        let info_hash = InfoHash::new(Sha1::digest(info).into());
+        if info_hash != magnet.info_hash {
+            bail!(
+                "Metadata hashes to {}, expected {}",
+                info_hash,
+                magnet.info_hash
+            );
+        }
+
+        let info: Info = serde_bencode::from_bytes(info).context("Failed parsing metadata")?;
+        let torrent = Torrent {
+            announce: magnet.trackers.first().cloned().unwrap_or_default(),
+            announce_list: (magnet.trackers.len() > 1).then(|| vec![magnet.trackers.clone()]),
+            creation_date: None,
+            comment: None,
+            created_by: None,
+            info,
+            info_hash: Some(info_hash),
+        };
+        torrent.validate().context("Invalid metadata")?;
+        Ok(torrent)
+    }
+}
+
+impl FromStr for Magnet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+fn parse_info_hash(hash: &str) -> anyhow::Result<InfoHash> {
+    let bytes = match hash.len() {
+        40 => return hash.parse(),
+        32 => decode_base32(hash).context("Invalid base32 info hash")?,
+        length => bail!("Info hash has {} characters, expected 40 or 32", length),
+    };
+
+    bytes
+        .try_into()
+        .map(InfoHash::new)
+        .map_err(|_| anyhow::anyhow!("Info hash is not 20 bytes"))
+}
+
+/// RFC 4648 base32 without padding, as used by older magnet links.
+fn decode_base32(input: &str) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() * 5 / 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in input.bytes() {
+        let value = match c.to_ascii_uppercase() {
+            c @ b'A'..=b'Z' => c - b'A',
+            c @ b'2'..=b'7' => c - b'2' + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEBIAN_INFO_HASH: &str = "1bd088ee9166a062cf4af09cf99720fa6e1a3133";
+
+    #[test]
+    fn test_parse_hex_magnet() {
+        let magnet = Magnet::parse(&format!(
+            "magnet:?xt=urn:btih:{}&dn=debian-12.7.0-amd64-netinst.iso\
+             &tr=http%3A%2F%2Fbttracker.debian.org%3A6969%2Fannounce\
+             &tr=udp%3A%2F%2Ftracker.test%3A80",
+            DEBIAN_INFO_HASH
+        ))
+        .unwrap();
+
+        assert_eq!(magnet.info_hash.to_hex(), DEBIAN_INFO_HASH);
+        assert_eq!(
+            magnet.display_name.as_deref(),
+            Some("debian-12.7.0-amd64-netinst.iso")
+        );
+        assert_eq!(
+            magnet.trackers,
+            vec![
+                "http://bttracker.debian.org:6969/announce",
+                "udp://tracker.test:80"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_base32_magnet() {
+        let magnet: Magnet = "magnet:?xt=urn:btih:DPIIR3URM2QGFT2K6COPTFZA7JXBUMJT"
+            .parse()
+            .unwrap();
+        assert_eq!(magnet.info_hash.to_hex(), DEBIAN_INFO_HASH);
+    }
+
+    #[test]
+    fn test_parse_magnet_without_name() {
+        let magnet = Magnet::parse(&format!(
+            "magnet:?xt=urn:btih:{}",
+            DEBIAN_INFO_HASH.to_uppercase()
+        ))
+        .unwrap();
+        assert_eq!(magnet.info_hash.to_hex(), DEBIAN_INFO_HASH);
+        assert_eq!(magnet.display_name, None);
+        assert!(magnet.trackers.is_empty());
+    }
+
+    #[test]
+    fn test_from_metadata() {
+        let info = b"d6:lengthi100e4:name4:test12:piece lengthi64e6:pieces40:\
+                     AAAAAAAAAAAAAAAAAAAABBBBBBBBBBBBBBBBBBBBe";
+        let magnet = Magnet {
+            info_hash: InfoHash::new(Sha1::digest(info).into()),
+            display_name: None,
+            trackers: vec!["http://a/announce".into(), "udp://b:80".into()],
+        };
+
+        let torrent = Torrent::from_metadata(&magnet, info).unwrap();
+        assert_eq!(torrent.info.name, "test");
+        assert_eq!(torrent.length(), 100);
+        assert_eq!(torrent.info_hash, Some(magnet.info_hash));
+        assert_eq!(torrent.announce, "http://a/announce");
+        assert_eq!(torrent.trackers(), vec![magnet.trackers.clone()]);
+
+        // Metadata from another torrent
+        let other = Magnet {
+            info_hash: InfoHash::new([0; 20]),
+            ..magnet
+        };
+        assert!(Torrent::from_metadata(&other, info).is_err());
+    }
+
+    #[test]
+    fn test_parse_invalid_magnet() {
+        assert!(Magnet::parse("http://example.com/?xt=urn:btih:abc").is_err());
+        assert!(Magnet::parse("magnet:?dn=missing-hash").is_err());
+        assert!(Magnet::parse("magnet:?xt=urn:btih:1234").is_err());
+        assert!(Magnet::parse("magnet:?xt=urn:btih:DPIIR3URM2QGFT2K6COPTFZA7JXBUM11").is_err());
+    }
+}
@@ -1,19 +1,64 @@
-use anyhow::Context;
+use anyhow::{bail, Context};
 use core::fmt;
+use serde_bencode::value::Value;
 use serde_derive::{Deserialize, Serialize};
 use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::io::Write;
 use std::path::Path;
 
+mod create;
+mod error;
+mod files;
 mod hashes;
+mod info_hash;
+mod magnet;
 
+pub use error::TorrentError;
+pub use files::{FileEntry, FileProgress};
 pub use hashes::Hashes;
+pub use info_hash::InfoHash;
+pub use magnet::Magnet;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Torrent {
     /// The URL of the tracker.
     pub announce: String,
+
+    /// Tiers of backup tracker URLs (BEP 12). When present, clients should use it instead of
+    /// `announce`.
+    #[serde(
+        default,
+        rename = "announce-list",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub announce_list: Option<Vec<Vec<String>>>,
+
+    /// The creation time of the torrent, in standard UNIX epoch format.
+    #[serde(
+        default,
+        rename = "creation date",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub creation_date: Option<i64>,
+
+    /// Free-form text from the torrent's author.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+
+    /// Name and version of the program that created the torrent.
+    #[serde(
+        default,
+        rename = "created by",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub created_by: Option<String>,
+
     pub info: Info,
-    pub info_hash: Option<[u8; 20]>,
+
+    /// SHA1 of the bencoded info dictionary, computed rather than read from the file.
+    #[serde(skip)]
+    pub info_hash: Option<InfoHash>,
 }
 
 impl Torrent {
@@ -30,45 +75,117 @@ impl Torrent {
 
         let hash: [u8; 20] = hasher.finalize().into();
 
-        self.info_hash = Some(hash);
+        self.info_hash = Some(InfoHash::new(hash));
 
         Ok(())
     }
 
     pub fn urlencode_infohash(&self) -> Option<String> {
-        self.info_hash.map(|info_hash| {
-            let mut encoded = String::with_capacity(info_hash.len() * 3);
-            info_hash.into_iter().for_each(|byte| {
-                encoded.push('%');
-                encoded.push_str(&format!("{:02X}", byte));
-            });
-            encoded
-        })
+        self.info_hash.map(|info_hash| info_hash.url_encode())
     }
     #[tracing::instrument]
     pub async fn open(file: impl AsRef<Path> + fmt::Debug) -> anyhow::Result<Self> {
         let file = tokio::fs::read(file)
             .await
             .context("Failed opening torrent file")?;
-        let mut t: Torrent =
-            serde_bencode::from_bytes(&file).context("Failed parsing torrent file")?;
-        t.get_info_hash().context("Failed to get info hash")?;
+        let t = Self::from_bytes(&file)?;
 
         tracing::info!("Succesfully opened {}", t.info.name);
         Ok(t)
     }
 
-    pub fn print_tree(&self) {
+    /// Parses and validates the contents of a .torrent file, e.g. downloaded or stored
+    /// elsewhere than on disk, and computes its info hash.
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        check_meta_version(bytes)?;
+        let mut t: Torrent =
+            serde_bencode::from_bytes(bytes).context("Failed parsing torrent file")?;
+        t.validate().context("Invalid torrent file")?;
+        t.get_info_hash().context("Failed to get info hash")?;
+        Ok(t)
+    }
+
+    /// Checks that the piece hashes cover exactly the declared length, so piece sizes computed
+    /// from `piece_length` and `length` can be trusted.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.info.piece_length == 0 {
+            bail!("Piece length is 0");
+        }
+
+        match &self.info.keys {
+            Keys::SingleFile { length: 0 } => bail!("File length is 0"),
+            Keys::SingleFile { .. } => {}
+            Keys::MultiFile { files } => {
+                if files.is_empty() {
+                    bail!("Torrent has no files");
+                }
+                if let Some(file) = files.iter().find(|file| file.length == 0) {
+                    bail!("File {} has length 0", file.path.join("/"));
+                }
+            }
+        }
+
+        let expected_pieces = self.length().div_ceil(self.info.piece_length);
+        if self.info.pieces.0.len() != expected_pieces {
+            bail!(
+                "Torrent has {} piece hashes, expected {} for {} bytes in pieces of {} bytes",
+                self.info.pieces.0.len(),
+                expected_pieces,
+                self.length(),
+                self.info.piece_length
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Parses a magnet link. The info dictionary isn't part of it and has to be fetched from
+    /// peers with `Peer::fetch_metadata`, so this only yields the info hash, name and trackers.
+    pub fn from_magnet(uri: &str) -> anyhow::Result<Magnet> {
+        Magnet::parse(uri)
+    }
+
+    /// Tracker tiers in the order they should be tried, falling back to the single `announce`
+    /// URL when there is no (non-empty) announce list.
+    pub fn trackers(&self) -> Vec<Vec<String>> {
+        match &self.announce_list {
+            Some(tiers) if tiers.iter().any(|tier| !tier.is_empty()) => tiers
+                .iter()
+                .filter(|tier| !tier.is_empty())
+                .cloned()
+                .collect(),
+            _ => vec![vec![self.announce.clone()]],
+        }
+    }
+
+    /// Free-form text from the torrent's author, if any.
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /// Name and version of the program that created the torrent, if recorded.
+    pub fn created_by(&self) -> Option<&str> {
+        self.created_by.as_deref()
+    }
+
+    /// Whether DHT and PEX are disabled for this torrent (BEP 27).
+    pub fn is_private(&self) -> bool {
+        self.info.private == Some(1)
+    }
+
+    /// Writes the path of every file in the torrent to `out`, one per line.
+    pub fn print_tree(&self, out: &mut impl Write) -> std::io::Result<()> {
         match &self.info.keys {
             Keys::SingleFile { .. } => {
-                eprintln!("{}", self.info.name);
+                writeln!(out, "{}", self.info.name)?;
             }
             Keys::MultiFile { files } => {
                 for file in files {
-                    eprintln!("{}", file.path.join(std::path::MAIN_SEPARATOR_STR));
+                    writeln!(out, "{}", file.path.join(std::path::MAIN_SEPARATOR_STR))?;
                 }
             }
         }
+        Ok(())
     }
 
     pub fn length(&self) -> usize {
@@ -77,6 +194,69 @@ impl Torrent {
             Keys::MultiFile { files } => files.iter().map(|file| file.length).sum(),
         }
     }
+
+    /// Machine-readable summary of the torrent's metadata, with the info hash as lowercase hex.
+    pub fn to_json(&self) -> serde_json::Value {
+        let files: Vec<serde_json::Value> = match &self.info.keys {
+            Keys::SingleFile { length } => {
+                vec![serde_json::json!({ "path": [self.info.name], "length": length })]
+            }
+            Keys::MultiFile { files } => files
+                .iter()
+                .map(|file| serde_json::json!({ "path": file.path, "length": file.length }))
+                .collect(),
+        };
+
+        serde_json::json!({
+            "name": self.info.name,
+            "total_length": self.length(),
+            "piece_length": self.info.piece_length,
+            "piece_count": self.info.pieces.0.len(),
+            "info_hash": self.info_hash.map(|info_hash| info_hash.to_hex()),
+            "announce": self.announce,
+            "announce_list": self.announce_list,
+            "creation_date": self.creation_date,
+            "comment": self.comment,
+            "created_by": self.created_by,
+            "files": files,
+        })
+    }
+
+    /// Size of the piece at `index`. Every piece is `piece_length` bytes except the last, which
+    /// holds the remainder. Indices past the last piece have size 0.
+    pub fn piece_len(&self, index: usize) -> u32 {
+        let start = index.saturating_mul(self.info.piece_length);
+        let end = start
+            .saturating_add(self.info.piece_length)
+            .min(self.length());
+        end.saturating_sub(start) as u32
+    }
+}
+
+/// Rejects v2-only torrents before parsing them as v1, which would either fail on the missing
+/// `pieces` with a confusing error or, worse, hash a dictionary that isn't their real identity.
+fn check_meta_version(file: &[u8]) -> anyhow::Result<()> {
+    #[derive(Deserialize)]
+    struct MetaVersion {
+        info: MetaVersionInfo,
+    }
+
+    #[derive(Deserialize)]
+    struct MetaVersionInfo {
+        #[serde(default, rename = "meta version")]
+        meta_version: Option<i64>,
+        #[serde(default)]
+        pieces: Option<serde::de::IgnoredAny>,
+    }
+
+    // Anything that doesn't match is left for the full parse to report
+    let Ok(probe) = serde_bencode::from_bytes::<MetaVersion>(file) else {
+        return Ok(());
+    };
+    if probe.info.meta_version == Some(2) && probe.info.pieces.is_none() {
+        return Err(TorrentError::UnsupportedV2.into());
+    }
+    Ok(())
 }
 
 // Structure mainly from https://github.com/jonhoo/codecrafters-bittorrent-rust/blob/master/src/torrent.rs
@@ -102,8 +282,31 @@ pub struct Info {
     /// Each entry of `pieces` is the SHA1 hash of the piece at the corresponding index.
     pub pieces: Hashes,
 
+    /// Set to 1 on private torrents (BEP 27), which must only get peers from their trackers.
+    ///
+    /// Being part of `info`, it's covered by the info hash, so it is only serialized when present.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private: Option<u8>,
+
     #[serde(flatten)]
     pub keys: Keys,
+
+    /// Keys not modeled above, such as `source` or `name.utf-8`. They are kept so re-encoding
+    /// the info dictionary, and so the info hash, matches the original.
+    #[serde(flatten, deserialize_with = "deserialize_extra")]
+    pub extra: BTreeMap<String, Value>,
+}
+
+/// Collects the unmodeled info keys. `Keys` is untagged, so serde leaves `length` and `files` in
+/// the flattened entries after parsing it, they're dropped here to avoid encoding them twice.
+fn deserialize_extra<'de, D>(deserializer: D) -> Result<BTreeMap<String, Value>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let mut extra: BTreeMap<String, Value> = serde::Deserialize::deserialize(deserializer)?;
+    extra.remove("length");
+    extra.remove("files");
+    Ok(extra)
 }
 
 /// There is a key `length` or a key `files`, but not both or neither.
@@ -131,3 +334,67 @@ pub struct File {
     /// (a zero length list is an error case).
     pub path: Vec<String>,
 }
+
+#[cfg(test)]
+impl Torrent {
+    /// Single file torrent of `length` bytes announcing to `announce`, with zeroed piece hashes
+    /// and info hash.
+    pub(crate) fn test_single_file(announce: &str, length: usize, piece_length: usize) -> Self {
+        Self {
+            announce: announce.to_string(),
+            announce_list: None,
+            creation_date: None,
+            comment: None,
+            created_by: None,
+            info: Info {
+                name: "mock_torrent".to_string(),
+                piece_length,
+                pieces: Hashes(vec![[0u8; 20]; length.div_ceil(piece_length)]),
+                private: None,
+                keys: Keys::SingleFile { length },
+                extra: BTreeMap::new(),
+            },
+            info_hash: Some(InfoHash::new([0u8; 20])),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn torrent(length: usize, piece_length: usize) -> Torrent {
+        Torrent::test_single_file("http://tracker.test/announce", length, piece_length)
+    }
+
+    #[test]
+    fn test_piece_len() {
+        // (length, piece length, index, expected size)
+        let cases = [
+            // Zero pieces
+            (0, 16, 0, 0),
+            // A single piece smaller than the piece length
+            (10, 16, 0, 10),
+            (10, 16, 1, 0),
+            // Last piece exactly the piece length
+            (32, 16, 0, 16),
+            (32, 16, 1, 16),
+            (32, 16, 2, 0),
+            // Truncated last piece
+            (40, 16, 1, 16),
+            (40, 16, 2, 8),
+            (40, 16, usize::MAX, 0),
+        ];
+
+        for (length, piece_length, index, expected) in cases {
+            assert_eq!(
+                torrent(length, piece_length).piece_len(index),
+                expected,
+                "length {}, piece length {}, index {}",
+                length,
+                piece_length,
+                index
+            );
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::torrent::Torrent;
+
+/// The tracker tiers of a torrent, kept for the whole download (BEP 12). Each tier is shuffled
+/// once, and a tracker that answers moves to the front of its tier so it is asked first next
+/// time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnnounceList {
+    tiers: Vec<Vec<String>>,
+}
+
+impl AnnounceList {
+    pub fn new(torrent: &Torrent) -> Self {
+        Self::with_rng(torrent, &mut rand::thread_rng())
+    }
+
+    /// Shuffles the tiers with `rng`, so tests can pass a seeded one for a reproducible order.
+    pub fn with_rng<R: Rng + ?Sized>(torrent: &Torrent, rng: &mut R) -> Self {
+        let mut tiers = torrent.trackers();
+        for tier in &mut tiers {
+            tier.shuffle(rng);
+        }
+        Self { tiers }
+    }
+
+    /// Tracker tiers in the order they should be tried.
+    pub fn tiers(&self) -> &[Vec<String>] {
+        &self.tiers
+    }
+
+    /// Moves the tracker at `index` of `tier` to the front of its tier, keeping the order of
+    /// the others.
+    pub(super) fn promote(&mut self, tier: usize, index: usize) {
+        self.tiers[tier][..=index].rotate_right(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(names: &[&str]) -> Vec<String> {
+        names
+            .iter()
+            .map(|name| format!("http://{}", name))
+            .collect()
+    }
+
+    #[test]
+    fn test_promote() {
+        let mut list = AnnounceList {
+            tiers: vec![urls(&["a"]), urls(&["b", "c", "d"])],
+        };
+
+        list.promote(1, 2);
+        assert_eq!(list.tiers(), [urls(&["a"]), urls(&["d", "b", "c"])]);
+        list.promote(1, 0);
+        assert_eq!(list.tiers(), [urls(&["a"]), urls(&["d", "b", "c"])]);
+    }
+
+    #[test]
+    fn test_shuffle_keeps_tiers() {
+        let mut torrent = Torrent::test_single_file("http://a", 16, 16);
+        torrent.announce_list = Some(vec![urls(&["a"]), urls(&["b", "c", "d"])]);
+
+        let list = AnnounceList::new(&torrent);
+        assert_eq!(list.tiers()[0], urls(&["a"]));
+        let mut second = list.tiers()[1].clone();
+        second.sort();
+        assert_eq!(second, urls(&["b", "c", "d"]));
+    }
+}
@@ -0,0 +1,11 @@
+/// Errors from trackers that answered, but not with anything usable.
+#[derive(Debug, thiserror::Error)]
+pub enum TrackerError {
+    /// The tracker refused the request, with its human-readable reason.
+    #[error("Tracker refused the announce: {0}")]
+    Failure(String),
+
+    /// Every tracker answered, but none of the announces returned a peer.
+    #[error("Trackers returned no peers after {0} announces")]
+    NoPeers(usize),
+}
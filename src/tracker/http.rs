@@ -0,0 +1,78 @@
+use anyhow::Context;
+use tokio::time::Duration;
+
+// Some trackers reject requests from clients they don't recognise
+pub(super) const DEFAULT_USER_AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Settings for the HTTP client shared by every announce and scrape.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// Bound on a whole request, from connecting to reading the body.
+    pub timeout: Duration,
+    /// Proxy URL all tracker requests go through, e.g. `socks5://127.0.0.1:9050`.
+    pub proxy: Option<String>,
+    /// Skips TLS certificate checks, for trackers with self-signed certificates.
+    pub danger_accept_invalid_certs: bool,
+    /// Overrides the `User-Agent` header, `torrent_rs/<version>` by default.
+    pub user_agent: Option<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(15),
+            proxy: None,
+            danger_accept_invalid_certs: false,
+            user_agent: None,
+        }
+    }
+}
+
+impl HttpConfig {
+    /// Builds a client to be cloned and reused, so announces share its connection pool.
+    pub fn build_client(&self) -> anyhow::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .user_agent(self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT))
+            .danger_accept_invalid_certs(self.danger_accept_invalid_certs);
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy).context("Invalid proxy URL")?);
+        }
+
+        builder.build().context("Failed to build HTTP client")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_proxy() {
+        let config = HttpConfig {
+            proxy: Some("not a url".to_string()),
+            ..Default::default()
+        };
+        assert!(config.build_client().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_user_agent_override() -> anyhow::Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+        let mock = mock_server
+            .mock("GET", "/")
+            .match_header("user-agent", "custom/1.0")
+            .create();
+
+        let client = HttpConfig {
+            user_agent: Some("custom/1.0".to_string()),
+            ..Default::default()
+        }
+        .build_client()?;
+        client.get(mock_server.url()).send().await?;
+
+        mock.assert();
+        Ok(())
+    }
+}
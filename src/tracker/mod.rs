@@ -1,30 +1,146 @@
 use anyhow::Context;
 use rand::Rng;
 use serde_derive::{Deserialize, Serialize};
-use tracing::{info, instrument};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use tokio::time::{sleep, Duration};
+use tracing::{info, instrument, warn};
 
-use crate::peer::PeerAddresses;
+use crate::peer::{PeerAddresses, PeerId, PeerList};
 use crate::torrent::Torrent;
 
+mod announce_list;
+mod error;
+mod http;
+mod reannounce;
+mod scrape;
+mod udp;
+
+pub use announce_list::AnnounceList;
+pub use error::TrackerError;
+pub use http::HttpConfig;
+pub use reannounce::AnnounceProgress;
+pub use scrape::ScrapeResponse;
+
+// Delay before announcing again after a failure or an empty peer list, doubled on every attempt
+const INITIAL_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(8);
+
+// Azureus-style client identifier at the start of every generated peer id
+const PEER_ID_PREFIX: &[u8] = b"-TR0001-";
+const _: () = assert!(PEER_ID_PREFIX.len() < 20);
+
+// Adds up to 25% so clients that failed together don't all retry at the same moment
+fn with_jitter(delay: Duration) -> Duration {
+    delay.mul_f64(rand::thread_rng().gen_range(1.0..1.25))
+}
+
 #[derive(Debug, Clone, Deserialize)]
+#[serde(from = "RawTrackerResponse")]
 pub struct TrackerResponse {
+    /// Why the tracker refused the request. When present, no other key is required.
+    pub failure_reason: Option<String>,
+
+    /// Like `failure_reason`, but the response is otherwise processed normally.
+    pub warning_message: Option<String>,
+
     /// An integer, indicating how often your client should make a request to the tracker in seconds.
+    /// Trackers that leave it out are re-announced to every 30 minutes.
     pub interval: usize,
 
+    /// Clients must not re-announce more often than this, in seconds.
+    pub min_interval: Option<usize>,
+
+    /// Opaque id to send back as `trackerid` on later announces to the same tracker.
+    pub tracker_id: Option<String>,
+
     /// A string, which contains list of peers that your client can connect to.
     ///
     /// Each peer is represented using 6 bytes. The first 4 bytes are the peer's IP address and the
-    /// last 2 bytes are the peer's port number.
-    #[serde(rename = "peers")]
+    /// last 2 bytes are the peer's port number. Trackers that ignore `compact=1` send a list of
+    /// dictionaries with `peer id`, `ip` and `port` keys instead, which is also accepted.
     pub peer_addresses: PeerAddresses,
+
+    /// IPv6 peers (BEP 7), each represented using 18 bytes: 16 for the IP address and 2 for the
+    /// port number.
+    pub peer_addresses6: PeerAddresses,
+
+    /// Peers of a dictionary model list given by DNS name, with their port. HTTP announces
+    /// resolve them into `peer_addresses`.
+    pub peer_hosts: Vec<(String, u16)>,
+}
+
+// TrackerResponse as bencoded, with the `peers` key not yet split into addresses and hosts
+#[derive(Deserialize)]
+struct RawTrackerResponse {
+    #[serde(default, rename = "failure reason")]
+    failure_reason: Option<String>,
+    #[serde(default, rename = "warning message")]
+    warning_message: Option<String>,
+    #[serde(default = "default_interval")]
+    interval: usize,
+    #[serde(default, rename = "min interval")]
+    min_interval: Option<usize>,
+    #[serde(default, rename = "tracker id")]
+    tracker_id: Option<String>,
+    #[serde(default)]
+    peers: PeerList,
+    #[serde(
+        default,
+        rename = "peers6",
+        deserialize_with = "PeerAddresses::deserialize_v6"
+    )]
+    peers6: PeerAddresses,
+}
+
+impl From<RawTrackerResponse> for TrackerResponse {
+    fn from(raw: RawTrackerResponse) -> Self {
+        Self {
+            failure_reason: raw.failure_reason,
+            warning_message: raw.warning_message,
+            interval: raw.interval,
+            min_interval: raw.min_interval,
+            tracker_id: raw.tracker_id,
+            peer_addresses: raw.peers.addresses,
+            peer_addresses6: raw.peers6,
+            peer_hosts: raw.peers.hosts,
+        }
+    }
+}
+
+fn default_interval() -> usize {
+    30 * 60
+}
+
+impl TrackerResponse {
+    /// Every peer in the response, IPv4 then IPv6.
+    pub fn peers(&self) -> impl Iterator<Item = &SocketAddr> {
+        self.peer_addresses
+            .iter()
+            .chain(self.peer_addresses6.iter())
+    }
+
+    /// Resolves `peer_hosts` and adds the first address of each to `peer_addresses`. Hosts
+    /// that don't resolve are skipped.
+    pub async fn resolve_peer_hosts(&mut self) {
+        for (host, port) in std::mem::take(&mut self.peer_hosts) {
+            match tokio::net::lookup_host((host.as_str(), port)).await {
+                Ok(mut addrs) => self.peer_addresses.0.extend(addrs.next()),
+                Err(e) => warn!(%host, "Failed to resolve peer: {}", e),
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct TrackerRequest {
     /// A unique identifier for your client.
     ///
-    /// A string of length 20 that you get to pick.
-    pub peer_id: String,
+    /// A string of length 20 that you get to pick. Appended percent-encoded by the announce, as
+    /// it can hold any bytes.
+    #[serde(skip)]
+    pub peer_id: PeerId,
 
     /// The port your client is listening on.
     /// Typically BitTorrent uses TCP port 6881-6889
@@ -44,22 +160,217 @@ pub struct TrackerRequest {
     /// The compact representation is more commonly used in the wild, the non-compact
     /// representation is mostly supported for backward-compatibility.
     pub compact: u8,
+
+    /// Random value fixed for the whole run, so trackers can recognise us across IP changes.
+    pub key: String,
+
+    /// Lifecycle event of the download, omitted for regular interval announces.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<TrackerEvent>,
+
+    /// The `tracker id` from a previous response of the same tracker.
+    #[serde(rename = "trackerid", skip_serializing_if = "Option::is_none")]
+    pub tracker_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackerEvent {
+    /// The first request to the tracker must include this.
+    Started,
+    /// Sent when the download completes, but not if it was already complete when started.
+    Completed,
+    /// Sent when the client is shutting down gracefully.
+    Stopped,
 }
 
 impl TrackerRequest {
-    fn build_request(torrent: &Torrent) -> anyhow::Result<Self> {
+    fn build_request(
+        peer_id: PeerId,
+        progress: AnnounceProgress,
+        event: Option<TrackerEvent>,
+    ) -> anyhow::Result<Self> {
         Ok(TrackerRequest {
-            peer_id: Self::generate_peer_id(),
+            peer_id,
             port: 6889,
-            uploaded: 0,
-            downloaded: 0,
-            left: torrent.length(),
+            uploaded: progress.uploaded,
+            downloaded: progress.downloaded,
+            left: progress.left,
             compact: 1,
+            key: format!("{:08X}", Self::session_key()),
+            event,
+            tracker_id: None,
         })
     }
-    #[instrument(skip(torrent))]
-    pub async fn announce(torrent: &Torrent) -> anyhow::Result<TrackerResponse> {
-        let request = Self::build_request(torrent).context("Failed to build request")?;
+    /// Announces to every tracker tier of `announce_list` in order (BEP 12) and returns the first
+    /// successful response. The tracker that answered moves to the front of its tier, so reuse
+    /// the list for later announces. `peer_id` must stay the same for the whole download, so trackers and peers see
+    /// a single client.
+    #[instrument(skip(client, torrent))]
+    pub async fn announce_tiers(
+        client: &reqwest::Client,
+        torrent: &Torrent,
+        announce_list: &mut AnnounceList,
+        peer_id: PeerId,
+        event: Option<TrackerEvent>,
+    ) -> anyhow::Result<TrackerResponse> {
+        let (_, response) =
+            Self::announce_tiers_with(client, torrent, announce_list, peer_id, |_| event).await?;
+        Ok(response)
+    }
+
+    /// Like `announce_tiers`, with the event picked for each tracker by `event_for`. Also returns
+    /// the URL of the tracker that answered.
+    async fn announce_tiers_with(
+        client: &reqwest::Client,
+        torrent: &Torrent,
+        announce_list: &mut AnnounceList,
+        peer_id: PeerId,
+        event_for: impl Fn(&str) -> Option<TrackerEvent>,
+    ) -> anyhow::Result<(String, TrackerResponse)> {
+        let mut last_error = None;
+        for tier in 0..announce_list.tiers().len() {
+            for index in 0..announce_list.tiers()[tier].len() {
+                let url = announce_list.tiers()[tier][index].clone();
+                match Self::announce(client, torrent, peer_id, &url, event_for(&url)).await {
+                    Ok(response) => {
+                        announce_list.promote(tier, index);
+                        return Ok((url, response));
+                    }
+                    Err(e) => {
+                        warn!(tracker = %url, "Announce failed: {:#}", e);
+                        last_error = Some(e);
+                    }
+                }
+            }
+        }
+
+        Err(last_error
+            .unwrap_or_else(|| anyhow::anyhow!("Torrent has no trackers"))
+            .context("All trackers failed"))
+    }
+
+    /// Like `announce_tiers`, but announces again with backoff while the trackers return no
+    /// peers, as some only list peers from the second announce on. Fails with
+    /// `TrackerError::NoPeers` if all `max_attempts` come back empty.
+    #[instrument(skip(client, torrent))]
+    pub async fn announce_for_peers(
+        client: &reqwest::Client,
+        torrent: &Torrent,
+        peer_id: PeerId,
+        max_attempts: usize,
+    ) -> anyhow::Result<TrackerResponse> {
+        let mut delay = INITIAL_RETRY_DELAY;
+        // Every tracker gets `started` until it has answered once, failed announces don't count
+        let mut started: HashSet<String> = HashSet::new();
+        let mut announce_list = AnnounceList::new(torrent);
+        for attempt in 1..=max_attempts {
+            let (url, response) =
+                Self::announce_tiers_with(client, torrent, &mut announce_list, peer_id, |url| {
+                    (!started.contains(url)).then_some(TrackerEvent::Started)
+                })
+                .await?;
+            started.insert(url);
+            if response.peers().next().is_some() {
+                return Ok(response);
+            }
+
+            if attempt < max_attempts {
+                warn!(
+                    "Trackers returned no peers, announcing again in {:?}",
+                    delay
+                );
+                sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+        }
+
+        Err(TrackerError::NoPeers(max_attempts).into())
+    }
+
+    /// Like `announce`, but retries up to `max_attempts` times with exponential backoff, e.g. when
+    /// the tracker is briefly unreachable. A tracker that answers with a failure reason is not
+    /// asked again. Every attempt carries `event`, as the tracker hasn't seen it until one
+    /// succeeds.
+    #[instrument(skip(client, torrent))]
+    pub async fn announce_with_retry(
+        client: &reqwest::Client,
+        torrent: &Torrent,
+        peer_id: PeerId,
+        url: &str,
+        event: Option<TrackerEvent>,
+        max_attempts: usize,
+    ) -> anyhow::Result<TrackerResponse> {
+        let mut delay = INITIAL_RETRY_DELAY;
+        let mut attempt = 1;
+
+        loop {
+            match Self::announce(client, torrent, peer_id, url, event).await {
+                Ok(response) => return Ok(response),
+                Err(e) if attempt < max_attempts && e.downcast_ref::<TrackerError>().is_none() => {
+                    let wait = with_jitter(delay);
+                    warn!(
+                        tracker = %url,
+                        attempt,
+                        "Announce failed, retrying in {:?}: {:#}",
+                        wait,
+                        e
+                    );
+                    sleep(wait).await;
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(e).context(format!("Announce failed after {} attempts", attempt))
+                }
+            }
+        }
+    }
+
+    /// Announces a fresh download, nothing transferred yet and the whole torrent left. `event`
+    /// should be `Started` for the first announce to `url` only.
+    pub async fn announce(
+        client: &reqwest::Client,
+        torrent: &Torrent,
+        peer_id: PeerId,
+        url: &str,
+        event: Option<TrackerEvent>,
+    ) -> anyhow::Result<TrackerResponse> {
+        Self::announce_with_progress(
+            client,
+            torrent,
+            peer_id,
+            url,
+            AnnounceProgress {
+                uploaded: 0,
+                downloaded: 0,
+                left: torrent.length(),
+            },
+            event,
+            None,
+        )
+        .await
+    }
+
+    /// Announces with the current transfer totals so re-announces report true progress, and the
+    /// `tracker_id` the tracker gave us, if any.
+    #[instrument(skip(client, torrent))]
+    pub async fn announce_with_progress(
+        client: &reqwest::Client,
+        torrent: &Torrent,
+        peer_id: PeerId,
+        url: &str,
+        progress: AnnounceProgress,
+        event: Option<TrackerEvent>,
+        tracker_id: Option<&str>,
+    ) -> anyhow::Result<TrackerResponse> {
+        let mut request =
+            Self::build_request(peer_id, progress, event).context("Failed to build request")?;
+        request.tracker_id = tracker_id.map(str::to_owned);
+        if url.starts_with("udp://") {
+            return Self::announce_udp(torrent, url, &request).await;
+        }
+
         let params = serde_urlencoded::to_string(&request)
             .context("Failed to encode tracker url params!")?;
         let info_hash_urlencoded = torrent
@@ -67,11 +378,16 @@ impl TrackerRequest {
             .context("Failed to urlencode infohash")?;
 
         let tracker_url = format!(
-            "{}?{}&info_hash={}",
-            torrent.announce, params, info_hash_urlencoded,
+            "{}?{}&info_hash={}&peer_id={}",
+            url,
+            params,
+            info_hash_urlencoded,
+            request.peer_id.url_encode()
         );
 
-        let response = reqwest::get(tracker_url)
+        let response = client
+            .get(tracker_url)
+            .send()
             .await
             .context("Failed to make GET request to tracker server!")?;
         let response = response
@@ -79,45 +395,66 @@ impl TrackerRequest {
             .await
             .context("Failed converting tracker response into bytes!")?;
 
-        let response: TrackerResponse = serde_bencode::from_bytes(&response)
+        let mut response: TrackerResponse = serde_bencode::from_bytes(&response)
             .context("Failed to deserialize tracker response!")?;
+        if let Some(reason) = response.failure_reason {
+            return Err(TrackerError::Failure(reason).into());
+        }
+        if let Some(warning) = &response.warning_message {
+            warn!(tracker = %url, "Tracker warning: {}", warning);
+        }
+        response.resolve_peer_hosts().await;
 
         info!("Sucesfully retrieved peers from tracker");
 
         Ok(response)
     }
 
-    pub fn generate_peer_id() -> String {
-        let mut rng = rand::thread_rng();
-        let prefix = "-TR0001-";
-        let mut peer_id = String::with_capacity(20);
-        peer_id.push_str(prefix);
+    /// The `key` sent with every announce, generated once per process.
+    pub fn session_key() -> u32 {
+        static KEY: OnceLock<u32> = OnceLock::new();
+        *KEY.get_or_init(rand::random)
+    }
+
+    pub fn generate_peer_id() -> PeerId {
+        Self::generate_peer_id_with_rng(&mut rand::thread_rng())
+    }
+
+    /// Generates a peer id from `rng`, so tests can pass a seeded one for a reproducible id.
+    pub fn generate_peer_id_with_rng<R: Rng + ?Sized>(rng: &mut R) -> PeerId {
+        let mut peer_id = [0u8; 20];
+        peer_id[..PEER_ID_PREFIX.len()].copy_from_slice(PEER_ID_PREFIX);
 
         // Fill the rest with alphanumeric characters
-        for _ in prefix.len()..20 {
-            let char = match rng.gen_range(0..3) {
-                0 => rng.gen_range(b'A'..=b'Z') as char,
-                1 => rng.gen_range(b'a'..=b'z') as char,
-                _ => rng.gen_range(b'0'..=b'9') as char,
+        for byte in &mut peer_id[PEER_ID_PREFIX.len()..] {
+            *byte = match rng.gen_range(0..3) {
+                0 => rng.gen_range(b'A'..=b'Z'),
+                1 => rng.gen_range(b'a'..=b'z'),
+                _ => rng.gen_range(b'0'..=b'9'),
             };
-            peer_id.push(char);
         }
 
-        peer_id
+        PeerId::new(peer_id)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    const PEER_ID: PeerId = PeerId::new(*b"-TR0001-abcdefghijkl");
+
+    // The event comes between key and the appended info hash, so this only matches announces
+    // without one
+    fn no_event() -> mockito::Matcher {
+        mockito::Matcher::Regex(r"key=[0-9A-F]{8}&info_hash=".to_string())
+    }
     use anyhow::{Ok, Result};
-    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::net::Ipv4Addr;
     use tokio;
 
     #[tokio::test]
     async fn test_announce_success() -> Result<()> {
-        use crate::torrent::{Hashes, Info, Keys, Torrent};
-
         let mut mock_server = mockito::Server::new_async().await;
 
         let peers = [
@@ -138,20 +475,16 @@ mod tests {
             .with_body(response_body)
             .create();
 
-        let torrent = Torrent {
-            announce: format!("{}/announce", mock_server.url()),
-            info: Info {
-                name: "mock_torrent".to_string(),
-                piece_length: 256 * 1024, // 256 KB
-                pieces: Hashes(vec![[0u8; 20]]),
-                keys: Keys::SingleFile {
-                    length: 1024 * 1024, // 1 MB
-                },
-            },
-            info_hash: Some([0u8; 20]), // Mock 20-byte info hash
-        };
+        let torrent = mock_torrent(format!("{}/announce", mock_server.url()));
 
-        let result = TrackerRequest::announce(&torrent).await;
+        let result = TrackerRequest::announce(
+            &reqwest::Client::new(),
+            &torrent,
+            PEER_ID,
+            &torrent.announce,
+            Some(TrackerEvent::Started),
+        )
+        .await;
 
         assert!(result.is_ok());
         let response = result.unwrap();
@@ -159,12 +492,514 @@ mod tests {
         assert_eq!(response.interval, 900);
 
         let expected_peers = PeerAddresses(vec![
-            SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 123), 6881),
-            SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 6889),
+            SocketAddr::new(Ipv4Addr::new(192, 0, 2, 123).into(), 6881),
+            SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), 6889),
         ]);
         assert_eq!(response.peer_addresses, expected_peers);
 
         mock.assert();
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_announce_resolves_peer_hosts() -> Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+        let mock = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::Any)
+            .with_body("d8:intervali900e5:peersld2:ip9:localhost4:porti6881eeee")
+            .create();
+
+        let torrent = mock_torrent(format!("{}/announce", mock_server.url()));
+        let response = TrackerRequest::announce(
+            &reqwest::Client::new(),
+            &torrent,
+            PEER_ID,
+            &torrent.announce,
+            Some(TrackerEvent::Started),
+        )
+        .await?;
+
+        assert!(response.peer_hosts.is_empty());
+        let peers: Vec<&SocketAddr> = response.peers().collect();
+        assert_eq!(peers.len(), 1);
+        assert!(peers[0].ip().is_loopback());
+        assert_eq!(peers[0].port(), 6881);
+
+        mock.assert();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_announce_tiers_failover() -> Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+
+        let mut response_body = Vec::new();
+        response_body.extend_from_slice(b"d8:intervali900e5:peers6:");
+        response_body.extend_from_slice(&[127, 0, 0, 1, 0x1A, 0xE1]);
+        response_body.extend_from_slice(b"e");
+
+        let mock = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::Any)
+            .expect(1)
+            .with_status(200)
+            .with_body(response_body)
+            .create();
+
+        // Nothing listens on port 1, so the first tier fails to connect
+        let unreachable = "http://127.0.0.1:1/announce".to_string();
+        let reachable = format!("{}/announce", mock_server.url());
+
+        let mut torrent = mock_torrent(unreachable.clone());
+        torrent.announce_list = Some(vec![vec![unreachable], vec![reachable]]);
+
+        let response = TrackerRequest::announce_tiers(
+            &reqwest::Client::new(),
+            &torrent,
+            &mut AnnounceList::new(&torrent),
+            PEER_ID,
+            Some(TrackerEvent::Started),
+        )
+        .await?;
+        assert_eq!(
+            response.peer_addresses,
+            PeerAddresses(vec![SocketAddr::new(
+                Ipv4Addr::new(127, 0, 0, 1).into(),
+                6881
+            )])
+        );
+
+        mock.assert();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_announce_tiers_promotes_working_tracker() -> Result<()> {
+        let mut failing_server = mockito::Server::new_async().await;
+        let mut working_server = mockito::Server::new_async().await;
+
+        // Depending on the shuffle, the failing tracker is asked at most once
+        let failing = failing_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::Any)
+            .expect_at_most(1)
+            .with_status(500)
+            .create();
+        let working = working_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::Any)
+            .expect(2)
+            .with_body("d8:intervali900e5:peers0:e")
+            .create();
+
+        let failing_url = format!("{}/announce", failing_server.url());
+        let working_url = format!("{}/announce", working_server.url());
+        let mut torrent = mock_torrent(failing_url.clone());
+        torrent.announce_list = Some(vec![vec![failing_url.clone(), working_url.clone()]]);
+
+        let client = reqwest::Client::new();
+        let mut announce_list = AnnounceList::new(&torrent);
+        for _ in 0..2 {
+            TrackerRequest::announce_tiers(&client, &torrent, &mut announce_list, PEER_ID, None)
+                .await?;
+            assert_eq!(
+                announce_list.tiers(),
+                [vec![working_url.clone(), failing_url.clone()]]
+            );
+        }
+
+        failing.assert();
+        working.assert();
+        Ok(())
+    }
+
+    fn mock_torrent(url: String) -> Torrent {
+        Torrent::test_single_file(&url, 1024 * 1024, 256 * 1024)
+    }
+
+    #[tokio::test]
+    async fn test_announce_for_peers_retries_empty() -> Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+
+        let mut response_body = Vec::new();
+        response_body.extend_from_slice(b"d8:intervali900e5:peers6:");
+        response_body.extend_from_slice(&[127, 0, 0, 1, 0x1A, 0xE1]);
+        response_body.extend_from_slice(b"e");
+
+        // Mocks with hits left are matched first, so the empty list is only served once
+        let empty = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "event".into(),
+                "started".into(),
+            ))
+            .expect(1)
+            .with_body("d8:intervali900e5:peers0:e")
+            .create();
+        let populated = mock_server
+            .mock("GET", "/announce")
+            .match_query(no_event())
+            .expect(1)
+            .with_body(response_body)
+            .create();
+
+        let torrent = mock_torrent(format!("{}/announce", mock_server.url()));
+        let response =
+            TrackerRequest::announce_for_peers(&reqwest::Client::new(), &torrent, PEER_ID, 3)
+                .await?;
+        assert_eq!(response.peers().count(), 1);
+
+        empty.assert();
+        populated.assert();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_announce_for_peers_starts_each_tracker() -> Result<()> {
+        let mut first_server = mockito::Server::new_async().await;
+        let mut second_server = mockito::Server::new_async().await;
+
+        let mut response_body = Vec::new();
+        response_body.extend_from_slice(b"d8:intervali900e5:peers6:");
+        response_body.extend_from_slice(&[127, 0, 0, 1, 0x1A, 0xE1]);
+        response_body.extend_from_slice(b"e");
+
+        // The first tier only answers once, then fails over to the second tier
+        let first = first_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "event".into(),
+                "started".into(),
+            ))
+            .expect(1)
+            .with_body("d8:intervali900e5:peers0:e")
+            .create();
+        let first_again = first_server
+            .mock("GET", "/announce")
+            .match_query(no_event())
+            .expect(1)
+            .with_status(500)
+            .create();
+        let second = second_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "event".into(),
+                "started".into(),
+            ))
+            .expect(1)
+            .with_body(response_body)
+            .create();
+
+        let first_url = format!("{}/announce", first_server.url());
+        let second_url = format!("{}/announce", second_server.url());
+        let mut torrent = mock_torrent(first_url.clone());
+        torrent.announce_list = Some(vec![vec![first_url], vec![second_url]]);
+
+        let response =
+            TrackerRequest::announce_for_peers(&reqwest::Client::new(), &torrent, PEER_ID, 2)
+                .await?;
+        assert_eq!(response.peers().count(), 1);
+
+        first.assert();
+        first_again.assert();
+        second.assert();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_announce_for_peers_gives_up() -> Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+        let mock = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::Any)
+            .expect(2)
+            .with_body("d8:intervali900e5:peers0:e")
+            .create();
+
+        let torrent = mock_torrent(format!("{}/announce", mock_server.url()));
+        let error =
+            TrackerRequest::announce_for_peers(&reqwest::Client::new(), &torrent, PEER_ID, 2)
+                .await
+                .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<TrackerError>(),
+            Some(TrackerError::NoPeers(2))
+        ));
+
+        mock.assert();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_announce_with_retry_backs_off() -> Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+
+        let mut response_body = Vec::new();
+        response_body.extend_from_slice(b"d8:intervali900e5:peers6:");
+        response_body.extend_from_slice(&[127, 0, 0, 1, 0x1A, 0xE1]);
+        response_body.extend_from_slice(b"e");
+
+        let failing = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::Any)
+            .expect(2)
+            .with_status(500)
+            .create();
+        // The failed attempts never reached the tracker, so the retry still says `started`
+        let working = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "event".into(),
+                "started".into(),
+            ))
+            .expect(1)
+            .with_body(response_body)
+            .create();
+
+        let torrent = mock_torrent(format!("{}/announce", mock_server.url()));
+        let url = torrent.announce.clone();
+        let started = std::time::Instant::now();
+        let response = TrackerRequest::announce_with_retry(
+            &reqwest::Client::new(),
+            &torrent,
+            PEER_ID,
+            &url,
+            Some(TrackerEvent::Started),
+            3,
+        )
+        .await?;
+        assert_eq!(response.peers().count(), 1);
+
+        // Waited 500ms then 1s, each with up to 25% jitter
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(1500), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(3000), "{:?}", elapsed);
+
+        failing.assert();
+        working.assert();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_announce_with_retry_stops_on_failure_reason() -> Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+        let mock = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::Any)
+            .expect(1)
+            .with_body("d14:failure reason18:torrent not authede")
+            .create();
+
+        let torrent = mock_torrent(format!("{}/announce", mock_server.url()));
+        let url = torrent.announce.clone();
+        assert!(TrackerRequest::announce_with_retry(
+            &reqwest::Client::new(),
+            &torrent,
+            PEER_ID,
+            &url,
+            Some(TrackerEvent::Started),
+            3
+        )
+        .await
+        .is_err());
+
+        mock.assert();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_announce_failure_reason() -> Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+        let mock = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::Any)
+            .with_body("d14:failure reason18:torrent not authede")
+            .create();
+
+        let torrent = mock_torrent(format!("{}/announce", mock_server.url()));
+        let url = torrent.announce.clone();
+        let error = TrackerRequest::announce(
+            &reqwest::Client::new(),
+            &torrent,
+            PEER_ID,
+            &url,
+            Some(TrackerEvent::Started),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<TrackerError>(),
+            Some(TrackerError::Failure(reason)) if reason == "torrent not authed"
+        ));
+
+        mock.assert();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_announce_times_out() -> Result<()> {
+        // Accepts the connection but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/announce", listener.local_addr()?);
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            sleep(Duration::from_secs(5)).await;
+            drop(stream);
+        });
+
+        let client = HttpConfig {
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        }
+        .build_client()?;
+        let torrent = mock_torrent(url.clone());
+        let started = std::time::Instant::now();
+        let error = TrackerRequest::announce(
+            &client,
+            &torrent,
+            PEER_ID,
+            &url,
+            Some(TrackerEvent::Started),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(error.chain().any(|cause| cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(reqwest::Error::is_timeout)));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_user_agent_and_stable_key() -> Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+        let mock = mock_server
+            .mock("GET", "/announce")
+            .match_header("user-agent", http::DEFAULT_USER_AGENT)
+            .match_query(mockito::Matcher::UrlEncoded(
+                "key".into(),
+                format!("{:08X}", TrackerRequest::session_key()),
+            ))
+            .expect(2)
+            .with_body("d8:intervali900e5:peers0:e")
+            .create();
+
+        let client = HttpConfig::default().build_client()?;
+        let torrent = mock_torrent(format!("{}/announce", mock_server.url()));
+        let url = torrent.announce.clone();
+        TrackerRequest::announce(
+            &client,
+            &torrent,
+            PEER_ID,
+            &url,
+            Some(TrackerEvent::Started),
+        )
+        .await?;
+        TrackerRequest::announce_with_progress(
+            &client,
+            &torrent,
+            PEER_ID,
+            &url,
+            AnnounceProgress::default(),
+            None,
+            None,
+        )
+        .await?;
+
+        mock.assert();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_announce_with_progress_query() -> Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+
+        let mock = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("uploaded".into(), "512".into()),
+                mockito::Matcher::UrlEncoded("downloaded".into(), "262144".into()),
+                mockito::Matcher::UrlEncoded("left".into(), "786432".into()),
+            ]))
+            .expect(1)
+            .with_status(200)
+            .with_body(b"d8:intervali900e5:peers0:e")
+            .create();
+
+        let torrent = mock_torrent(format!("{}/announce", mock_server.url()));
+
+        TrackerRequest::announce_with_progress(
+            &reqwest::Client::new(),
+            &torrent,
+            PEER_ID,
+            &torrent.announce,
+            AnnounceProgress {
+                uploaded: 512,
+                downloaded: 256 * 1024,
+                left: 768 * 1024,
+            },
+            None,
+            None,
+        )
+        .await?;
+
+        mock.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_generate_peer_id_seeded() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let first = TrackerRequest::generate_peer_id_with_rng(&mut StdRng::seed_from_u64(7));
+        let second = TrackerRequest::generate_peer_id_with_rng(&mut StdRng::seed_from_u64(7));
+        assert_eq!(first, second);
+
+        let other = TrackerRequest::generate_peer_id_with_rng(&mut StdRng::seed_from_u64(8));
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn test_generate_peer_id_format() {
+        for _ in 0..100 {
+            let peer_id = TrackerRequest::generate_peer_id();
+            let bytes = peer_id.as_bytes();
+            assert_eq!(bytes.len(), 20);
+            assert!(bytes.starts_with(PEER_ID_PREFIX));
+            assert!(bytes[PEER_ID_PREFIX.len()..]
+                .iter()
+                .all(u8::is_ascii_alphanumeric));
+        }
+    }
+
+    #[test]
+    fn test_event_query_string() -> Result<()> {
+        let cases = [
+            (None, None),
+            (Some(TrackerEvent::Started), Some("event=started")),
+            (Some(TrackerEvent::Completed), Some("event=completed")),
+            (Some(TrackerEvent::Stopped), Some("event=stopped")),
+        ];
+
+        for (event, expected) in cases {
+            let request = TrackerRequest::build_request(
+                PEER_ID,
+                AnnounceProgress {
+                    left: 100,
+                    ..Default::default()
+                },
+                event,
+            )?;
+            let params = serde_urlencoded::to_string(&request)?;
+            match expected {
+                Some(expected) => assert!(params.ends_with(expected), "{}", params),
+                None => assert!(!params.contains("event"), "{}", params),
+            }
+        }
+        Ok(())
+    }
 }
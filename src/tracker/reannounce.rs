@@ -0,0 +1,430 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+
+use super::{TrackerEvent, TrackerRequest, TrackerResponse};
+use crate::peer::{PeerAddresses, PeerId};
+use crate::torrent::Torrent;
+
+// Guard against trackers answering with a zero or tiny interval
+const MIN_INTERVAL: Duration = Duration::from_secs(60);
+
+// The interval doubles on every consecutive failed re-announce, up to this
+const MAX_FAILURE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait before the next announce: the tracker's interval, but never less than its
+/// `min interval`.
+fn next_interval(response: &TrackerResponse) -> Duration {
+    let seconds = response.interval.max(response.min_interval.unwrap_or(0));
+    Duration::from_secs(seconds as u64).max(MIN_INTERVAL)
+}
+
+/// Transfer totals reported to the tracker on each re-announce.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AnnounceProgress {
+    pub uploaded: usize,
+    pub downloaded: usize,
+    pub left: usize,
+}
+
+impl TrackerRequest {
+    /// Spawns a task that re-announces to `url` every tracker interval, starting after
+    /// `interval` (at least `MIN_INTERVAL`), and sends peers it hasn't reported before on
+    /// `peers_tx`.
+    ///
+    /// The first announce after `left` drops to zero carries the `completed` event. The task
+    /// stops when `shutdown` fires, sending a final `stopped` announce, or when the receiving end
+    /// of `peers_tx` is dropped.
+    ///
+    /// `peer_id` must be the one used for the initial announce, so the tracker sees one client.
+    #[allow(clippy::too_many_arguments)]
+    pub fn spawn_reannounce(
+        client: reqwest::Client,
+        torrent: Torrent,
+        peer_id: PeerId,
+        url: String,
+        interval: Duration,
+        progress: watch::Receiver<AnnounceProgress>,
+        peers_tx: mpsc::Sender<PeerAddresses>,
+        mut shutdown: broadcast::Receiver<()>,
+        known_peers: HashSet<SocketAddr>,
+    ) -> JoinHandle<()> {
+        // Completed must not be sent if the download was already complete when started
+        let mut was_complete = progress.borrow().left == 0;
+
+        tokio::spawn(async move {
+            // The failure backoff doubles this, which would keep a zero interval at zero
+            let mut interval = interval.max(MIN_INTERVAL);
+            let mut known_peers = known_peers;
+            let mut tracker_id: Option<String> = None;
+            let mut failures: u32 = 0;
+
+            loop {
+                tokio::select! {
+                    _ = sleep(interval) => {}
+                    _ = shutdown.recv() => {
+                        info!(tracker = %url, "Stopping re-announce");
+                        let current = *progress.borrow();
+                        if let Err(e) = Self::announce_with_progress(
+                            &client,
+                            &torrent,
+                            peer_id,
+                            &url,
+                            current,
+                            Some(TrackerEvent::Stopped),
+                            tracker_id.as_deref(),
+                        )
+                        .await
+                        {
+                            warn!(tracker = %url, "Stopped announce failed: {:#}", e);
+                        }
+                        return;
+                    }
+                }
+
+                let current = *progress.borrow();
+                let event = if current.left == 0 && !was_complete {
+                    Some(TrackerEvent::Completed)
+                } else {
+                    None
+                };
+
+                let response = match Self::announce_with_progress(
+                    &client,
+                    &torrent,
+                    peer_id,
+                    &url,
+                    current,
+                    event,
+                    tracker_id.as_deref(),
+                )
+                .await
+                {
+                    Ok(response) => response,
+                    Err(e) => {
+                        failures += 1;
+                        interval = (interval * 2).min(MAX_FAILURE_INTERVAL);
+                        warn!(
+                            tracker = %url,
+                            failures,
+                            "Re-announce failed, next attempt in {:?}: {:#}",
+                            interval,
+                            e
+                        );
+                        continue;
+                    }
+                };
+
+                if event.is_some() {
+                    was_complete = true;
+                }
+                failures = 0;
+
+                interval = next_interval(&response);
+                if response.tracker_id.is_some() {
+                    tracker_id = response.tracker_id.clone();
+                }
+
+                let new_peers: Vec<SocketAddr> = response
+                    .peers()
+                    .filter(|addr| known_peers.insert(**addr))
+                    .copied()
+                    .collect();
+
+                if new_peers.is_empty() {
+                    continue;
+                }
+
+                info!("Re-announce discovered {} new peers", new_peers.len());
+                if peers_tx.send(PeerAddresses(new_peers)).await.is_err() {
+                    return;
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const PEER_ID: PeerId = PeerId::new(*b"-TR0001-abcdefghijkl");
+
+    fn mock_torrent(announce: String) -> Torrent {
+        Torrent::test_single_file(&announce, 1024 * 1024, 256 * 1024)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reannounce_forwards_only_new_peers() -> anyhow::Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+
+        let mut response_body = Vec::new();
+        response_body.extend_from_slice(b"d8:intervali1e5:peers12:");
+        response_body.extend_from_slice(&[192, 0, 2, 123, 0x1A, 0xE1]);
+        response_body.extend_from_slice(&[127, 0, 0, 1, 0x1A, 0xE9]);
+        response_body.extend_from_slice(b"e");
+
+        let mock = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::Any)
+            .expect_at_least(2)
+            .with_status(200)
+            .with_body(response_body.clone())
+            .create();
+        let stopped = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "event".into(),
+                "stopped".into(),
+            ))
+            .expect(1)
+            .with_status(200)
+            .with_body(response_body)
+            .create();
+
+        let url = format!("{}/announce", mock_server.url());
+        let (_progress_tx, progress_rx) = watch::channel(AnnounceProgress::default());
+        let (peers_tx, mut peers_rx) = mpsc::channel(8);
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        // One of the peers is already connected and must not be reported again
+        let connected = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), 6889);
+
+        let handle = TrackerRequest::spawn_reannounce(
+            reqwest::Client::new(),
+            mock_torrent(url.clone()),
+            PEER_ID,
+            url,
+            Duration::from_millis(10),
+            progress_rx,
+            peers_tx,
+            shutdown_rx,
+            HashSet::from([connected]),
+        );
+
+        let peers = peers_rx
+            .recv()
+            .await
+            .expect("First re-announce yields peers");
+        assert_eq!(
+            peers,
+            PeerAddresses(vec![SocketAddr::new(
+                Ipv4Addr::new(192, 0, 2, 123).into(),
+                6881
+            )])
+        );
+
+        // Wait for the second announce, which only returns already known peers
+        sleep(MIN_INTERVAL * 2 + Duration::from_secs(30)).await;
+        shutdown_tx.send(())?;
+        handle.await?;
+
+        assert!(peers_rx.try_recv().is_err());
+        mock.assert();
+        stopped.assert();
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_interval_respects_min_interval() -> anyhow::Result<()> {
+        let response: TrackerResponse =
+            serde_bencode::from_bytes(b"d8:intervali100e12:min intervali300e5:peers0:e")?;
+        assert_eq!(response.min_interval, Some(300));
+        assert_eq!(next_interval(&response), Duration::from_secs(300));
+
+        let response: TrackerResponse =
+            serde_bencode::from_bytes(b"d8:intervali600e12:min intervali300e5:peers0:e")?;
+        assert_eq!(next_interval(&response), Duration::from_secs(600));
+
+        let response: TrackerResponse = serde_bencode::from_bytes(b"d8:intervali0e5:peers0:e")?;
+        assert_eq!(next_interval(&response), MIN_INTERVAL);
+
+        let response: TrackerResponse = serde_bencode::from_bytes(b"d5:peers0:e")?;
+        assert_eq!(next_interval(&response), Duration::from_secs(30 * 60));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reannounce_zero_interval_backs_off() -> anyhow::Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+
+        // One failed re-announce after MIN_INTERVAL, the next one is two intervals later, plus
+        // the stopped announce
+        let failing = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::Any)
+            .expect(2)
+            .with_status(500)
+            .create();
+
+        let url = format!("{}/announce", mock_server.url());
+        let (_progress_tx, progress_rx) = watch::channel(AnnounceProgress::default());
+        let (peers_tx, _peers_rx) = mpsc::channel(8);
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        let handle = TrackerRequest::spawn_reannounce(
+            reqwest::Client::new(),
+            mock_torrent(url.clone()),
+            PEER_ID,
+            url,
+            Duration::ZERO,
+            progress_rx,
+            peers_tx,
+            shutdown_rx,
+            HashSet::new(),
+        );
+
+        sleep(MIN_INTERVAL * 2 + Duration::from_secs(30)).await;
+        shutdown_tx.send(())?;
+        handle.await?;
+
+        failing.assert();
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reannounce_echoes_tracker_id() -> anyhow::Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+
+        let first = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::Any)
+            .with_status(200)
+            .with_body(b"d8:intervali1e5:peers0:10:tracker id3:abce")
+            .create();
+        let echoed = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "trackerid".into(),
+                "abc".into(),
+            ))
+            .expect_at_least(1)
+            .with_status(200)
+            .with_body(b"d8:intervali1e5:peers0:e")
+            .create();
+
+        let url = format!("{}/announce", mock_server.url());
+        let (_progress_tx, progress_rx) = watch::channel(AnnounceProgress::default());
+        let (peers_tx, _peers_rx) = mpsc::channel(8);
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        let handle = TrackerRequest::spawn_reannounce(
+            reqwest::Client::new(),
+            mock_torrent(url.clone()),
+            PEER_ID,
+            url,
+            Duration::from_millis(10),
+            progress_rx,
+            peers_tx,
+            shutdown_rx,
+            HashSet::new(),
+        );
+
+        sleep(MIN_INTERVAL + Duration::from_secs(30)).await;
+        shutdown_tx.send(())?;
+        handle.await?;
+
+        first.assert();
+        echoed.assert();
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reannounce_keeps_peer_id() -> anyhow::Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+
+        let other_peer_id = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::Any)
+            .expect(0)
+            .create();
+        let same_peer_id = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "peer_id".into(),
+                PEER_ID.to_string(),
+            ))
+            .expect_at_least(2)
+            .with_status(200)
+            .with_body(b"d8:intervali1e5:peers0:e")
+            .create();
+
+        let url = format!("{}/announce", mock_server.url());
+        let (_progress_tx, progress_rx) = watch::channel(AnnounceProgress::default());
+        let (peers_tx, _peers_rx) = mpsc::channel(8);
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        let handle = TrackerRequest::spawn_reannounce(
+            reqwest::Client::new(),
+            mock_torrent(url.clone()),
+            PEER_ID,
+            url,
+            Duration::from_millis(10),
+            progress_rx,
+            peers_tx,
+            shutdown_rx,
+            HashSet::new(),
+        );
+
+        sleep(MIN_INTERVAL + Duration::from_secs(30)).await;
+        shutdown_tx.send(())?;
+        handle.await?;
+
+        // Periodic and stopped announces all carry the same id
+        same_peer_id.assert();
+        other_peer_id.assert();
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reannounce_sends_completed_once() -> anyhow::Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+
+        let completed = mock_server
+            .mock("GET", "/announce")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "event".into(),
+                "completed".into(),
+            ))
+            .expect(1)
+            .with_status(200)
+            .with_body(b"d8:intervali1e5:peers0:e")
+            .create();
+
+        let url = format!("{}/announce", mock_server.url());
+        let (progress_tx, progress_rx) = watch::channel(AnnounceProgress {
+            uploaded: 0,
+            downloaded: 1024,
+            left: 0,
+        });
+        let (peers_tx, _peers_rx) = mpsc::channel(8);
+        let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+
+        // Download was still in progress when the task started
+        progress_tx.send_modify(|progress| progress.left = 1024);
+        let handle = TrackerRequest::spawn_reannounce(
+            reqwest::Client::new(),
+            mock_torrent(url.clone()),
+            PEER_ID,
+            url,
+            Duration::from_millis(10),
+            progress_rx,
+            peers_tx,
+            shutdown_rx,
+            HashSet::new(),
+        );
+        progress_tx.send_modify(|progress| progress.left = 0);
+
+        sleep(MIN_INTERVAL + Duration::from_secs(30)).await;
+        shutdown_tx.send(())?;
+        handle.await?;
+
+        completed.assert();
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Context};
+use serde_bytes::ByteBuf;
+use serde_derive::Deserialize;
+use tracing::{info, instrument};
+
+use super::{TrackerError, TrackerRequest};
+use crate::torrent::Torrent;
+
+/// Swarm statistics for a single torrent, as returned by the tracker scrape convention.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ScrapeResponse {
+    /// Number of peers with the entire file, i.e. seeders.
+    pub complete: usize,
+
+    /// Total number of times the tracker has registered a completion.
+    pub downloaded: usize,
+
+    /// Number of non-seeder peers, aka "leechers".
+    pub incomplete: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct ScrapeFiles {
+    /// Why the tracker refused the scrape. When present, `files` may be missing.
+    #[serde(default, rename = "failure reason")]
+    failure_reason: Option<String>,
+
+    /// Keyed by the raw 20 byte info hash.
+    #[serde(default)]
+    files: HashMap<ByteBuf, ScrapeResponse>,
+}
+
+impl TrackerRequest {
+    #[instrument(skip(client, torrent))]
+    pub async fn scrape(
+        client: &reqwest::Client,
+        torrent: &Torrent,
+        url: &str,
+    ) -> anyhow::Result<ScrapeResponse> {
+        let scrape_url = scrape_url(url)?;
+        let info_hash = torrent.info_hash.context("Info hash is not computed")?;
+        let info_hash_urlencoded = torrent
+            .urlencode_infohash()
+            .context("Failed to urlencode infohash")?;
+
+        // Private trackers keep the passkey in the query of the announce URL
+        let separator = if scrape_url.contains('?') { '&' } else { '?' };
+        let response = client
+            .get(format!(
+                "{}{}info_hash={}",
+                scrape_url, separator, info_hash_urlencoded
+            ))
+            .send()
+            .await
+            .context("Failed to make GET request to tracker server!")?;
+        let response = response
+            .bytes()
+            .await
+            .context("Failed converting scrape response into bytes!")?;
+
+        let mut response: ScrapeFiles = serde_bencode::from_bytes(&response)
+            .context("Failed to deserialize scrape response!")?;
+        if let Some(reason) = response.failure_reason {
+            return Err(TrackerError::Failure(reason).into());
+        }
+
+        let stats = response
+            .files
+            .remove(&ByteBuf::from(info_hash.as_bytes().to_vec()))
+            .context("Scrape response doesn't include the torrent")?;
+
+        info!("Sucesfully scraped tracker");
+
+        Ok(stats)
+    }
+}
+
+/// Derives the scrape URL by replacing the `announce` at the start of the final path segment
+/// with `scrape`. Trackers whose announce URL doesn't follow that convention don't support
+/// scraping.
+fn scrape_url(announce_url: &str) -> anyhow::Result<String> {
+    if announce_url.starts_with("udp://") {
+        bail!("Scraping UDP trackers is not supported");
+    }
+
+    let (base, last_segment) = announce_url
+        .rsplit_once('/')
+        .context("Announce URL has no path")?;
+
+    match last_segment.strip_prefix("announce") {
+        Some(rest) => Ok(format!("{}/scrape{}", base, rest)),
+        None => bail!("Tracker {} does not support scraping", announce_url),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::InfoHash;
+
+    #[test]
+    fn test_scrape_url() {
+        assert_eq!(
+            scrape_url("http://example.com/announce").unwrap(),
+            "http://example.com/scrape"
+        );
+        assert_eq!(
+            scrape_url("http://example.com/x/announce.php").unwrap(),
+            "http://example.com/x/scrape.php"
+        );
+        assert!(scrape_url("http://example.com/a").is_err());
+        assert!(scrape_url("http://example.com/announce?x=2/4").is_err());
+        assert_eq!(
+            scrape_url("http://example.com/announce?passkey=abc").unwrap(),
+            "http://example.com/scrape?passkey=abc"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scrape_success() -> anyhow::Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+
+        let info_hash = [7u8; 20];
+        let mut response_body = Vec::new();
+        response_body.extend_from_slice(b"d5:filesd20:");
+        response_body.extend_from_slice(&info_hash);
+        response_body.extend_from_slice(b"d8:completei5e10:downloadedi50e10:incompletei10eeee");
+
+        let mock = mock_server
+            .mock("GET", "/scrape")
+            .match_query(mockito::Matcher::Any)
+            .expect(1)
+            .with_status(200)
+            .with_body(response_body)
+            .create();
+
+        let mut torrent = Torrent::test_single_file(
+            &format!("{}/announce", mock_server.url()),
+            1024 * 1024,
+            256 * 1024,
+        );
+        torrent.info_hash = Some(InfoHash::new(info_hash));
+
+        let response =
+            TrackerRequest::scrape(&reqwest::Client::new(), &torrent, &torrent.announce).await?;
+        assert_eq!(
+            response,
+            ScrapeResponse {
+                complete: 5,
+                downloaded: 50,
+                incomplete: 10,
+            }
+        );
+
+        mock.assert();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scrape_passkey_url() -> anyhow::Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+
+        let mut response_body = Vec::new();
+        response_body.extend_from_slice(b"d5:filesd20:");
+        response_body.extend_from_slice(&[0u8; 20]);
+        response_body.extend_from_slice(b"d8:completei1e10:downloadedi2e10:incompletei3eeee");
+
+        let mock = mock_server
+            .mock("GET", "/scrape")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("passkey".into(), "abc".into()),
+                mockito::Matcher::Regex(format!("&info_hash={}$", "%00".repeat(20))),
+            ]))
+            .expect(1)
+            .with_body(response_body)
+            .create();
+
+        let url = format!("{}/announce?passkey=abc", mock_server.url());
+        let torrent = Torrent::test_single_file(&url, 1024 * 1024, 256 * 1024);
+
+        let response = TrackerRequest::scrape(&reqwest::Client::new(), &torrent, &url).await?;
+        assert_eq!(response.complete, 1);
+
+        mock.assert();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scrape_failure_reason() -> anyhow::Result<()> {
+        let mut mock_server = mockito::Server::new_async().await;
+        let mock = mock_server
+            .mock("GET", "/scrape")
+            .match_query(mockito::Matcher::Any)
+            .expect(1)
+            .with_body("d14:failure reason18:torrent not authede")
+            .create();
+
+        let url = format!("{}/announce", mock_server.url());
+        let torrent = Torrent::test_single_file(&url, 1024 * 1024, 256 * 1024);
+
+        let error = TrackerRequest::scrape(&reqwest::Client::new(), &torrent, &url)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<TrackerError>(),
+            Some(TrackerError::Failure(reason)) if reason == "torrent not authed"
+        ));
+
+        mock.assert();
+        Ok(())
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::{bail, Context};
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Duration, Instant};
+use tracing::{debug, info, instrument};
+
+use super::{TrackerError, TrackerEvent, TrackerRequest, TrackerResponse};
+use crate::peer::PeerAddresses;
+use crate::torrent::Torrent;
+
+// https://www.bittorrent.org/beps/bep_0015.html
+const PROTOCOL_ID: u64 = 0x41727101980;
+const ACTION_CONNECT: u32 = 0;
+const ACTION_ANNOUNCE: u32 = 1;
+const ACTION_ERROR: u32 = 3;
+
+const CONNECT_RESPONSE_LENGTH: usize = 16;
+const ANNOUNCE_RESPONSE_HEADER_LENGTH: usize = 20;
+// Large enough for any UDP datagram, so long peer lists are never truncated
+const MAX_PACKET_SIZE: usize = 65536;
+
+// A request is retransmitted after 15 * 2^n seconds, n going from 0 up to 8
+const INITIAL_TIMEOUT: Duration = Duration::from_secs(15);
+const MAX_RETRANSMISSIONS: u32 = 8;
+// Trackers accept a connection id for one minute after sending it
+const CONNECTION_ID_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Connection ids obtained from each tracker address, with the time they were received.
+fn connection_ids() -> &'static Mutex<HashMap<SocketAddr, (u64, Instant)>> {
+    static IDS: OnceLock<Mutex<HashMap<SocketAddr, (u64, Instant)>>> = OnceLock::new();
+    IDS.get_or_init(Default::default)
+}
+
+impl TrackerRequest {
+    #[instrument(skip(torrent, request))]
+    pub(super) async fn announce_udp(
+        torrent: &Torrent,
+        url: &str,
+        request: &TrackerRequest,
+    ) -> anyhow::Result<TrackerResponse> {
+        let url = reqwest::Url::parse(url).context("Invalid tracker URL")?;
+        let host = url.host_str().context("Tracker URL has no host")?;
+        let port = url.port().context("UDP tracker URL has no port")?;
+        let info_hash = torrent.info_hash.context("Info hash is not computed")?;
+
+        let tracker_addr = tokio::net::lookup_host((host, port))
+            .await
+            .context("Failed to resolve tracker host")?
+            .next()
+            .context("Tracker host has no addresses")?;
+
+        let bind_addr = if tracker_addr.is_ipv6() {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let socket = UdpSocket::bind(bind_addr)
+            .await
+            .context("Failed to bind UDP socket")?;
+        socket
+            .connect(tracker_addr)
+            .await
+            .context("Failed to connect UDP socket to tracker")?;
+
+        let mut attempt = 0;
+        let response = loop {
+            // Connect step, skipped while the last connection id is still valid
+            let cached = connection_ids()
+                .lock()
+                .unwrap()
+                .get(&tracker_addr)
+                .filter(|(_, received)| received.elapsed() < CONNECTION_ID_LIFETIME)
+                .map(|(connection_id, _)| *connection_id);
+            let connection_id = match cached {
+                Some(connection_id) => connection_id,
+                None => {
+                    let transaction_id: u32 = rand::random();
+                    let mut connect_request = Vec::with_capacity(16);
+                    connect_request.extend_from_slice(&PROTOCOL_ID.to_be_bytes());
+                    connect_request.extend_from_slice(&ACTION_CONNECT.to_be_bytes());
+                    connect_request.extend_from_slice(&transaction_id.to_be_bytes());
+
+                    let Some(response) =
+                        Self::udp_round_trip(&socket, &connect_request, transaction_id, attempt)
+                            .await?
+                    else {
+                        attempt = next_attempt(attempt)?;
+                        continue;
+                    };
+                    check_header(&response, ACTION_CONNECT, transaction_id)?;
+                    if response.len() < CONNECT_RESPONSE_LENGTH {
+                        bail!("Connect response is {} bytes", response.len());
+                    }
+                    let connection_id = u64::from_be_bytes(response[8..16].try_into()?);
+                    connection_ids()
+                        .lock()
+                        .unwrap()
+                        .insert(tracker_addr, (connection_id, Instant::now()));
+                    connection_id
+                }
+            };
+
+            // Announce step
+            let transaction_id: u32 = rand::random();
+            let mut announce_request = Vec::with_capacity(98);
+            announce_request.extend_from_slice(&connection_id.to_be_bytes());
+            announce_request.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes());
+            announce_request.extend_from_slice(&transaction_id.to_be_bytes());
+            announce_request.extend_from_slice(info_hash.as_bytes());
+            announce_request.extend_from_slice(request.peer_id.as_bytes());
+            announce_request.extend_from_slice(&(request.downloaded as u64).to_be_bytes());
+            announce_request.extend_from_slice(&(request.left as u64).to_be_bytes());
+            announce_request.extend_from_slice(&(request.uploaded as u64).to_be_bytes());
+            announce_request.extend_from_slice(&event_code(request.event).to_be_bytes());
+            announce_request.extend_from_slice(&0u32.to_be_bytes()); // IP: use sender address
+            announce_request.extend_from_slice(&Self::session_key().to_be_bytes());
+            announce_request.extend_from_slice(&(-1i32).to_be_bytes()); // Number of peers wanted: default
+            announce_request.extend_from_slice(&request.port.to_be_bytes());
+
+            match Self::udp_round_trip(&socket, &announce_request, transaction_id, attempt).await? {
+                Some(response) => {
+                    check_header(&response, ACTION_ANNOUNCE, transaction_id)?;
+                    break response;
+                }
+                None => attempt = next_attempt(attempt)?,
+            }
+        };
+
+        if response.len() < ANNOUNCE_RESPONSE_HEADER_LENGTH {
+            bail!("Announce response is {} bytes", response.len());
+        }
+
+        let interval = u32::from_be_bytes(response[8..12].try_into()?) as usize;
+        // Trackers reached over IPv6 answer with 18-byte IPv6 entries
+        let (peer_addresses, peer_addresses6) = if tracker_addr.is_ipv6() {
+            let peers = PeerAddresses::from_compact_v6(&response[20..])
+                .context("Invalid peer list in UDP announce response")?;
+            (PeerAddresses::default(), peers)
+        } else {
+            let peers = PeerAddresses::from_compact(&response[20..])
+                .context("Invalid peer list in UDP announce response")?;
+            (peers, PeerAddresses::default())
+        };
+
+        info!("Sucesfully retrieved peers from UDP tracker");
+
+        Ok(TrackerResponse {
+            failure_reason: None,
+            warning_message: None,
+            interval,
+            min_interval: None,
+            tracker_id: None,
+            peer_addresses,
+            peer_addresses6,
+            peer_hosts: Vec::new(),
+        })
+    }
+
+    /// Sends `packet` and waits 15 * 2^`attempt` seconds for the response carrying
+    /// `transaction_id`. Returns `None` on timeout so the caller can retransmit; responses to
+    /// other transactions, such as late answers to earlier attempts, are ignored.
+    async fn udp_round_trip(
+        socket: &UdpSocket,
+        packet: &[u8],
+        transaction_id: u32,
+        attempt: u32,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        socket
+            .send(packet)
+            .await
+            .context("Failed to send UDP tracker packet")?;
+
+        let deadline = Instant::now() + INITIAL_TIMEOUT * 2u32.pow(attempt);
+        let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+        loop {
+            let Ok(received) = timeout_at(deadline, socket.recv(&mut buffer)).await else {
+                debug!(attempt, "UDP tracker response timed out");
+                return Ok(None);
+            };
+            let length = received.context("Failed to receive UDP tracker response")?;
+            if length >= 8 && buffer[4..8] == transaction_id.to_be_bytes() {
+                buffer.truncate(length);
+                return Ok(Some(buffer));
+            }
+        }
+    }
+}
+
+fn next_attempt(attempt: u32) -> anyhow::Result<u32> {
+    if attempt >= MAX_RETRANSMISSIONS {
+        bail!(
+            "UDP tracker did not answer after {} retransmissions",
+            MAX_RETRANSMISSIONS
+        );
+    }
+    Ok(attempt + 1)
+}
+
+fn event_code(event: Option<TrackerEvent>) -> u32 {
+    match event {
+        None => 0,
+        Some(TrackerEvent::Completed) => 1,
+        Some(TrackerEvent::Started) => 2,
+        Some(TrackerEvent::Stopped) => 3,
+    }
+}
+
+fn check_header(response: &[u8], action: u32, transaction_id: u32) -> anyhow::Result<()> {
+    if response.len() < 8 {
+        bail!("UDP tracker response is {} bytes", response.len());
+    }
+
+    let response_action = u32::from_be_bytes(response[0..4].try_into()?);
+    let response_transaction_id = u32::from_be_bytes(response[4..8].try_into()?);
+
+    if response_transaction_id != transaction_id {
+        bail!("Transaction id mismatch in UDP tracker response");
+    }
+
+    if response_action == ACTION_ERROR {
+        let reason = String::from_utf8_lossy(&response[8..]).into_owned();
+        return Err(TrackerError::Failure(reason).into());
+    }
+
+    if response_action != action {
+        bail!(
+            "Unexpected action {} in UDP tracker response",
+            response_action
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::torrent::InfoHash;
+    use std::net::Ipv4Addr;
+
+    #[tokio::test]
+    async fn test_announce_udp_success() -> anyhow::Result<()> {
+        let server = UdpSocket::bind("127.0.0.1:0").await?;
+        let server_addr = server.local_addr()?;
+
+        let server_task = tokio::spawn(async move {
+            let mut buffer = [0u8; MAX_PACKET_SIZE];
+
+            let (length, client) = server.recv_from(&mut buffer).await.unwrap();
+            assert_eq!(length, 16);
+            assert_eq!(&buffer[0..8], &PROTOCOL_ID.to_be_bytes());
+            let mut response = Vec::new();
+            response.extend_from_slice(&ACTION_CONNECT.to_be_bytes());
+            response.extend_from_slice(&buffer[12..16]); // Transaction id
+            response.extend_from_slice(&42u64.to_be_bytes()); // Connection id
+            server.send_to(&response, client).await.unwrap();
+
+            let (length, client) = server.recv_from(&mut buffer).await.unwrap();
+            assert_eq!(length, 98);
+            assert_eq!(&buffer[0..8], &42u64.to_be_bytes());
+            assert_eq!(&buffer[16..36], &[7u8; 20]); // Info hash
+            assert_eq!(&buffer[80..84], &2u32.to_be_bytes()); // Event: started
+            let mut response = Vec::new();
+            response.extend_from_slice(&ACTION_ANNOUNCE.to_be_bytes());
+            response.extend_from_slice(&buffer[12..16]); // Transaction id
+            response.extend_from_slice(&1800u32.to_be_bytes()); // Interval
+            response.extend_from_slice(&3u32.to_be_bytes()); // Leechers
+            response.extend_from_slice(&5u32.to_be_bytes()); // Seeders
+            response.extend_from_slice(&[192, 0, 2, 123, 0x1A, 0xE1]);
+            response.extend_from_slice(&[127, 0, 0, 1, 0x1A, 0xE9]);
+            server.send_to(&response, client).await.unwrap();
+        });
+
+        let mut torrent = Torrent::test_single_file(
+            &format!("udp://{}/announce", server_addr),
+            1024 * 1024,
+            256 * 1024,
+        );
+        torrent.info_hash = Some(InfoHash::new([7u8; 20]));
+
+        let response = TrackerRequest::announce(
+            &reqwest::Client::new(),
+            &torrent,
+            TrackerRequest::generate_peer_id(),
+            &torrent.announce,
+            Some(TrackerEvent::Started),
+        )
+        .await?;
+        server_task.await?;
+
+        assert_eq!(response.interval, 1800);
+        assert_eq!(
+            response.peer_addresses,
+            PeerAddresses(vec![
+                SocketAddr::new(Ipv4Addr::new(192, 0, 2, 123).into(), 6881),
+                SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), 6889),
+            ])
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_announce_udp_retransmits_and_reuses_connection_id() -> anyhow::Result<()> {
+        let server = UdpSocket::bind("127.0.0.1:0").await?;
+        let server_addr = server.local_addr()?;
+
+        // Drops the first connect request, then answers the next five packets and returns the
+        // action of every packet it received
+        let server_task = tokio::spawn(async move {
+            let mut buffer = vec![0u8; MAX_PACKET_SIZE];
+            let mut actions = Vec::new();
+            while actions.len() < 6 {
+                let (_, client) = server.recv_from(&mut buffer).await.unwrap();
+                let action = u32::from_be_bytes(buffer[8..12].try_into().unwrap());
+                actions.push(action);
+                if actions.len() == 1 {
+                    continue;
+                }
+
+                let mut response = Vec::new();
+                response.extend_from_slice(&action.to_be_bytes());
+                response.extend_from_slice(&buffer[12..16]); // Transaction id
+                if action == ACTION_CONNECT {
+                    response.extend_from_slice(&42u64.to_be_bytes()); // Connection id
+                } else {
+                    assert_eq!(&buffer[0..8], &42u64.to_be_bytes());
+                    response.extend_from_slice(&1800u32.to_be_bytes()); // Interval
+                    response.extend_from_slice(&0u32.to_be_bytes()); // Leechers
+                    response.extend_from_slice(&0u32.to_be_bytes()); // Seeders
+                }
+                server.send_to(&response, client).await.unwrap();
+            }
+            actions
+        });
+
+        let mut torrent = Torrent::test_single_file(
+            &format!("udp://{}/announce", server_addr),
+            1024 * 1024,
+            256 * 1024,
+        );
+        torrent.info_hash = Some(InfoHash::new([7u8; 20]));
+        let client = reqwest::Client::new();
+        let announce = || {
+            TrackerRequest::announce(
+                &client,
+                &torrent,
+                TrackerRequest::generate_peer_id(),
+                &torrent.announce,
+                None,
+            )
+        };
+
+        let start = Instant::now();
+        announce().await?;
+        assert!(start.elapsed() >= INITIAL_TIMEOUT);
+
+        // The connection id is still valid, so the connect step is skipped
+        announce().await?;
+
+        tokio::time::sleep(CONNECTION_ID_LIFETIME).await;
+        announce().await?;
+
+        assert_eq!(
+            server_task.await?,
+            [
+                ACTION_CONNECT,
+                ACTION_CONNECT,
+                ACTION_ANNOUNCE,
+                ACTION_ANNOUNCE,
+                ACTION_CONNECT,
+                ACTION_ANNOUNCE,
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_attempt_gives_up() {
+        assert_eq!(next_attempt(0).unwrap(), 1);
+        assert_eq!(
+            next_attempt(MAX_RETRANSMISSIONS - 1).unwrap(),
+            MAX_RETRANSMISSIONS
+        );
+        assert!(next_attempt(MAX_RETRANSMISSIONS).is_err());
+    }
+
+    #[test]
+    fn test_check_header_error_action() {
+        let mut response = Vec::new();
+        response.extend_from_slice(&ACTION_ERROR.to_be_bytes());
+        response.extend_from_slice(&9u32.to_be_bytes());
+        response.extend_from_slice(b"unregistered torrent");
+
+        let error = check_header(&response, ACTION_ANNOUNCE, 9).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<TrackerError>(),
+            Some(TrackerError::Failure(reason)) if reason == "unregistered torrent"
+        ));
+    }
+}
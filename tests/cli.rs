@@ -0,0 +1,54 @@
+use std::process::Command;
+
+const EXAMPLE_TORRENT: &str = "example/debian-12.7.0-amd64-netinst.iso.torrent";
+
+#[test]
+fn test_info_prints_info_hash() {
+    let output = Command::new(env!("CARGO_BIN_EXE_torrent_rs"))
+        .args(["info", EXAMPLE_TORRENT])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Name: debian-12.7.0-amd64-netinst.iso"));
+    assert!(stdout.contains("Info hash: 1bd088ee9166a062cf4af09cf99720fa6e1a3133"));
+    assert!(stdout.contains("Created by: mktorrent 1.1"));
+}
+
+#[test]
+fn test_info_prints_files_to_stdout() {
+    let output = Command::new(env!("CARGO_BIN_EXE_torrent_rs"))
+        .args(["info", EXAMPLE_TORRENT])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Files:\ndebian-12.7.0-amd64-netinst.iso\n"));
+}
+
+#[test]
+fn test_info_json() {
+    let output = Command::new(env!("CARGO_BIN_EXE_torrent_rs"))
+        .args(["info", "--json", EXAMPLE_TORRENT])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        json["info_hash"],
+        "1bd088ee9166a062cf4af09cf99720fa6e1a3133"
+    );
+}
+
+#[test]
+fn test_download_is_not_supported() {
+    let output = Command::new(env!("CARGO_BIN_EXE_torrent_rs"))
+        .args(["download", EXAMPLE_TORRENT])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+}
@@ -0,0 +1,84 @@
+use sha1::{Digest, Sha1};
+use torrent_rs::torrent::{Keys, Torrent};
+
+const ANNOUNCE: &str = "http://tracker.test/announce";
+
+#[tokio::test]
+async fn test_create_multi_file_round_trip() -> anyhow::Result<()> {
+    let root = tempfile::tempdir()?;
+    let content = root.path().join("content");
+    std::fs::create_dir_all(content.join("sub"))?;
+    std::fs::write(content.join("b.txt"), vec![2u8; 5])?;
+    std::fs::write(content.join("a.txt"), vec![1u8; 3])?;
+    std::fs::write(content.join("sub").join("c.txt"), vec![3u8; 10])?;
+
+    let created = Torrent::create(&content, 4, ANNOUNCE).await?;
+
+    // 18 bytes in pieces of 4, the last one is short
+    assert_eq!(created.info.pieces.0.len(), 5);
+    let mut concatenated = vec![1u8; 3];
+    concatenated.extend_from_slice(&[2u8; 5]);
+    concatenated.extend_from_slice(&[3u8; 10]);
+    assert_eq!(
+        created.info.pieces.0[0],
+        <[u8; 20]>::from(Sha1::digest(&concatenated[..4]))
+    );
+    assert_eq!(
+        created.info.pieces.0[4],
+        <[u8; 20]>::from(Sha1::digest(&concatenated[16..]))
+    );
+
+    let torrent_path = root.path().join("content.torrent");
+    std::fs::write(&torrent_path, serde_bencode::to_bytes(&created)?)?;
+    let reopened = Torrent::open(&torrent_path).await?;
+
+    assert_eq!(reopened.info_hash, created.info_hash);
+    assert_eq!(reopened.info.name, "content");
+    assert_eq!(reopened.announce, ANNOUNCE);
+    match &reopened.info.keys {
+        Keys::MultiFile { files } => {
+            let paths: Vec<_> = files.iter().map(|file| file.path.join("/")).collect();
+            assert_eq!(paths, vec!["a.txt", "b.txt", "sub/c.txt"]);
+            let lengths: Vec<_> = files.iter().map(|file| file.length).collect();
+            assert_eq!(lengths, vec![3, 5, 10]);
+        }
+        Keys::SingleFile { .. } => panic!("Expected a multi-file torrent"),
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_skips_empty_files() -> anyhow::Result<()> {
+    let root = tempfile::tempdir()?;
+    let content = root.path().join("content");
+    std::fs::create_dir_all(&content)?;
+    std::fs::write(content.join("a.txt"), vec![1u8; 6])?;
+    std::fs::write(content.join("empty.txt"), [])?;
+
+    let created = Torrent::create(&content, 4, ANNOUNCE).await?;
+    match &created.info.keys {
+        Keys::MultiFile { files } => {
+            let paths: Vec<_> = files.iter().map(|file| file.path.join("/")).collect();
+            assert_eq!(paths, vec!["a.txt"]);
+        }
+        Keys::SingleFile { .. } => panic!("Expected a multi-file torrent"),
+    }
+    assert_eq!(created.info.pieces.0.len(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_create_single_file() -> anyhow::Result<()> {
+    let root = tempfile::tempdir()?;
+    let file = root.path().join("single.bin");
+    std::fs::write(&file, vec![7u8; 8])?;
+
+    // Exactly two full pieces, no short piece at the end
+    let created = Torrent::create(&file, 4, ANNOUNCE).await?;
+    assert_eq!(created.info.name, "single.bin");
+    assert_eq!(created.info.pieces.0.len(), 2);
+    assert!(matches!(created.info.keys, Keys::SingleFile { length: 8 }));
+
+    Ok(())
+}
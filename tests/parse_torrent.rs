@@ -1,6 +1,8 @@
 use anyhow::Ok;
+use sha1::{Digest, Sha1};
 use std::path::PathBuf;
-use torrent_rs::torrent::Torrent;
+use torrent_rs::piece::verify_piece;
+use torrent_rs::torrent::{InfoHash, Keys, Torrent, TorrentError};
 
 #[tokio::test]
 async fn test_torrent_file_parsing() -> anyhow::Result<()> {
@@ -24,11 +26,24 @@ async fn test_torrent_file_parsing() -> anyhow::Result<()> {
         "Torrent should have a valid total length"
     );
 
+    assert_eq!(torrent.creation_date, Some(1725105953));
+    assert_eq!(
+        torrent.comment(),
+        Some("\"Debian CD from cdimage.debian.org\"")
+    );
+    assert_eq!(torrent.created_by(), Some("mktorrent 1.1"));
+    assert!(torrent.announce_list.is_none());
+    assert!(matches!(torrent.info.keys, Keys::SingleFile { .. }));
+
     assert!(torrent.info_hash.is_some());
     assert_eq!(
         torrent.urlencode_infohash(),
         Some("%1B%D0%88%EE%91%66%A0%62%CF%4A%F0%9C%F9%97%20%FA%6E%1A%31%33".to_string())
     );
+    assert_eq!(
+        torrent.info_hash.map(|info_hash| info_hash.url_encode()),
+        torrent.urlencode_infohash()
+    );
 
     Ok(())
 }
@@ -44,3 +59,293 @@ async fn test_invalid_torrent_file_parsing() {
         "Non-existent torrent file should return an error"
     );
 }
+
+#[tokio::test]
+async fn test_piece_hashes_verify() -> anyhow::Result<()> {
+    let torrent = Torrent::open("example/debian-12.7.0-amd64-netinst.iso.torrent").await?;
+
+    // Without the ISO on disk, zeroed data must never pass verification
+    let data = vec![0u8; torrent.info.piece_length];
+    assert!(!verify_piece(&data, &torrent.info.pieces.0[0]));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_torrent_json() -> anyhow::Result<()> {
+    let torrent = Torrent::open("example/debian-12.7.0-amd64-netinst.iso.torrent").await?;
+    let json = torrent.to_json();
+
+    assert_eq!(json["name"], "debian-12.7.0-amd64-netinst.iso");
+    assert_eq!(
+        json["info_hash"],
+        "1bd088ee9166a062cf4af09cf99720fa6e1a3133"
+    );
+    assert_eq!(json["total_length"], torrent.length());
+    assert_eq!(json["piece_count"], torrent.info.pieces.0.len());
+    assert_eq!(
+        json["files"][0]["path"][0],
+        "debian-12.7.0-amd64-netinst.iso"
+    );
+
+    Ok(())
+}
+
+/// Index one past the end of the bencoded value starting at `start`.
+fn bencode_end(bytes: &[u8], start: usize) -> usize {
+    match bytes[start] {
+        b'i' => start + bytes[start..].iter().position(|&b| b == b'e').unwrap() + 1,
+        b'l' | b'd' => {
+            let mut position = start + 1;
+            while bytes[position] != b'e' {
+                position = bencode_end(bytes, position);
+            }
+            position + 1
+        }
+        _ => {
+            let colon = start + bytes[start..].iter().position(|&b| b == b':').unwrap();
+            let length: usize = std::str::from_utf8(&bytes[start..colon])
+                .unwrap()
+                .parse()
+                .unwrap();
+            colon + 1 + length
+        }
+    }
+}
+
+/// The raw info dictionary of a bencoded torrent.
+fn raw_info(bytes: &[u8]) -> &[u8] {
+    let key = bytes.windows(6).position(|w| w == b"4:info").unwrap();
+    let start = key + 6;
+    &bytes[start..bencode_end(bytes, start)]
+}
+
+#[tokio::test]
+async fn test_info_reencodes_byte_identical() -> anyhow::Result<()> {
+    let path = "example/debian-12.7.0-amd64-netinst.iso.torrent";
+    let bytes = std::fs::read(path)?;
+    let torrent = Torrent::open(path).await?;
+
+    let original = raw_info(&bytes);
+    let reencoded = serde_bencode::to_bytes(&torrent.info)?;
+
+    assert_eq!(reencoded, original);
+    assert_eq!(
+        torrent.info_hash,
+        Some(InfoHash::new(Sha1::digest(original).into()))
+    );
+
+    Ok(())
+}
+
+/// Bencodes a byte string, `<length>:<bytes>`.
+fn bstr(value: &str) -> String {
+    format!("{}:{}", value.len(), value)
+}
+
+#[test]
+fn test_multi_file_torrent_parsing() -> anyhow::Result<()> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"d8:announce");
+    bytes.extend_from_slice(bstr("http://tracker.test/announce").as_bytes());
+    bytes.extend_from_slice(b"13:announce-listll");
+    bytes.extend_from_slice(bstr("http://tracker.test/announce").as_bytes());
+    bytes.extend_from_slice(b"el");
+    bytes.extend_from_slice(bstr("udp://backup.test:80/ann").as_bytes());
+    bytes.extend_from_slice(b"ee13:creation datei1700000000e");
+    bytes.extend_from_slice(b"4:infod5:filesl");
+    bytes.extend_from_slice(b"d6:lengthi3e4:pathl5:a.txteed6:lengthi5e4:pathl3:sub5:b.txtee");
+    bytes.extend_from_slice(b"e4:name3:dir12:piece lengthi4e6:pieces40:");
+    bytes.extend_from_slice(&[1u8; 20]);
+    bytes.extend_from_slice(&[2u8; 20]);
+    bytes.extend_from_slice(b"ee");
+
+    let torrent: Torrent = serde_bencode::from_bytes(&bytes)?;
+
+    assert_eq!(torrent.info.name, "dir");
+    assert_eq!(torrent.creation_date, Some(1700000000));
+    assert_eq!(
+        torrent.trackers(),
+        vec![
+            vec!["http://tracker.test/announce".to_string()],
+            vec!["udp://backup.test:80/ann".to_string()],
+        ]
+    );
+    assert_eq!(torrent.length(), 8);
+    match &torrent.info.keys {
+        Keys::MultiFile { files } => {
+            assert_eq!(files.len(), 2);
+            assert_eq!(files[1].path, vec!["sub", "b.txt"]);
+        }
+        Keys::SingleFile { .. } => panic!("Expected a multi-file torrent"),
+    }
+
+    Ok(())
+}
+
+fn single_file_torrent(extra_info: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let mut info = Vec::new();
+    info.extend_from_slice(b"d6:lengthi5e4:name5:a.txt12:piece lengthi16384e6:pieces20:");
+    info.extend_from_slice(&[1u8; 20]);
+    info.extend_from_slice(extra_info);
+    info.extend_from_slice(b"e");
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"d8:announce");
+    bytes.extend_from_slice(bstr("http://tracker.test/announce").as_bytes());
+    bytes.extend_from_slice(b"4:info");
+    bytes.extend_from_slice(&info);
+    bytes.extend_from_slice(b"e");
+    (bytes, info)
+}
+
+#[test]
+fn test_private_flag() -> anyhow::Result<()> {
+    let (bytes, info) = single_file_torrent(b"7:privatei1e");
+    let mut torrent: Torrent = serde_bencode::from_bytes(&bytes)?;
+    torrent.get_info_hash()?;
+
+    assert!(torrent.is_private());
+    // The flag must survive re-encoding, or the hash wouldn't match the tracker's
+    assert_eq!(
+        torrent.info_hash,
+        Some(InfoHash::new(Sha1::digest(&info).into()))
+    );
+
+    let (bytes, info) = single_file_torrent(b"");
+    let mut torrent: Torrent = serde_bencode::from_bytes(&bytes)?;
+    torrent.get_info_hash()?;
+
+    assert!(!torrent.is_private());
+    assert_eq!(
+        torrent.info_hash,
+        Some(InfoHash::new(Sha1::digest(&info).into()))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_validate_piece_count() -> anyhow::Result<()> {
+    let (bytes, _) = single_file_torrent(b"");
+    let mut torrent: Torrent = serde_bencode::from_bytes(&bytes)?;
+    assert!(torrent.validate().is_ok());
+
+    torrent.info.pieces.0.push([2u8; 20]);
+    let error = torrent.validate().unwrap_err();
+    assert!(error.to_string().contains("2 piece hashes, expected 1"));
+
+    Ok(())
+}
+
+#[test]
+fn test_validate_rejects_zero_lengths() -> anyhow::Result<()> {
+    let (bytes, _) = single_file_torrent(b"");
+    let torrent: Torrent = serde_bencode::from_bytes(&bytes)?;
+
+    let mut zero_piece_length = torrent.clone();
+    zero_piece_length.info.piece_length = 0;
+    assert!(zero_piece_length.validate().is_err());
+
+    let mut zero_length = torrent;
+    zero_length.info.keys = Keys::SingleFile { length: 0 };
+    assert!(zero_length.validate().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_unknown_info_keys_round_trip() -> anyhow::Result<()> {
+    let (bytes, info) = single_file_torrent(b"6:source7:TRACKER");
+    let mut torrent: Torrent = serde_bencode::from_bytes(&bytes)?;
+    torrent.get_info_hash()?;
+
+    assert_eq!(torrent.info.extra.len(), 1);
+    assert!(torrent.info.extra.contains_key("source"));
+    assert_eq!(serde_bencode::to_bytes(&torrent.info)?, info);
+    assert_eq!(
+        torrent.info_hash,
+        Some(InfoHash::new(Sha1::digest(&info).into()))
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_v2_torrent_is_rejected() -> anyhow::Result<()> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"d8:announce");
+    bytes.extend_from_slice(bstr("http://tracker.test/announce").as_bytes());
+    bytes.extend_from_slice(b"4:infod9:file treed5:a.txtd0:d6:lengthi5e11:pieces root32:");
+    bytes.extend_from_slice(&[1u8; 32]);
+    bytes.extend_from_slice(b"eee12:meta versioni2e4:name5:a.txt12:piece lengthi16384eee");
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("v2.torrent");
+    std::fs::write(&path, bytes)?;
+
+    let error = Torrent::open(&path).await.unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<TorrentError>(),
+        Some(TorrentError::UnsupportedV2)
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_hybrid_torrent_opens_as_v1() -> anyhow::Result<()> {
+    // Hybrid torrents keep the v1 keys next to `meta version`
+    let (bytes, _) = single_file_torrent(b"12:meta versioni2e");
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("hybrid.torrent");
+    std::fs::write(&path, bytes)?;
+
+    let torrent = Torrent::open(&path).await?;
+    assert!(torrent.info.extra.contains_key("meta version"));
+    assert_eq!(torrent.info.pieces.0.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_metadata_outside_info_hash() -> anyhow::Result<()> {
+    let (bytes, info) = single_file_torrent(b"");
+    let split = bytes
+        .windows(6)
+        .position(|window| window == b"4:info")
+        .unwrap();
+
+    let mut annotated = bytes[..split].to_vec();
+    annotated
+        .extend_from_slice(b"7:comment5:hello10:created by9:mktorrent13:creation datei1700000000e");
+    annotated.extend_from_slice(&bytes[split..]);
+
+    let mut torrent: Torrent = serde_bencode::from_bytes(&annotated)?;
+    torrent.get_info_hash()?;
+    assert_eq!(torrent.comment(), Some("hello"));
+    assert_eq!(torrent.created_by(), Some("mktorrent"));
+    assert_eq!(torrent.creation_date, Some(1700000000));
+
+    // They live outside the info dictionary, so the hash is the same as without them
+    assert_eq!(
+        torrent.info_hash,
+        Some(InfoHash::new(Sha1::digest(&info).into()))
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_from_bytes_matches_open() -> anyhow::Result<()> {
+    let path = "example/debian-12.7.0-amd64-netinst.iso.torrent";
+    let opened = Torrent::open(path).await?;
+    let parsed = Torrent::from_bytes(&std::fs::read(path)?)?;
+
+    assert_eq!(parsed.info_hash, opened.info_hash);
+    assert_eq!(parsed.info.name, opened.info.name);
+    assert_eq!(parsed.length(), opened.length());
+
+    assert!(Torrent::from_bytes(b"not bencode").is_err());
+    Ok(())
+}
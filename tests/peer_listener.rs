@@ -0,0 +1,136 @@
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc};
+use torrent_rs::message::{Bitfield, PeerMessage};
+use torrent_rs::peer::{EncryptionPolicy, Peer};
+use torrent_rs::torrent::InfoHash;
+
+const INFO_HASH: InfoHash = InfoHash::new([3; 20]);
+const OUR_PEER_ID: &str = "-TR0001-listener0000";
+const REMOTE_PEER_ID: &str = "-XX0001-remotepeer00";
+const TOTAL_PIECES: usize = 4;
+
+async fn start_listener(
+    encryption: EncryptionPolicy,
+) -> anyhow::Result<(SocketAddr, mpsc::Receiver<Peer>, broadcast::Sender<()>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+
+    let (peers_tx, peers_rx) = mpsc::channel(4);
+    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    Peer::spawn_listener(
+        listener,
+        INFO_HASH,
+        OUR_PEER_ID.parse().unwrap(),
+        TOTAL_PIECES,
+        encryption,
+        peers_tx,
+        shutdown_rx,
+    );
+
+    Ok((addr, peers_rx, shutdown_tx))
+}
+
+#[tokio::test]
+async fn test_inbound_handshake() -> anyhow::Result<()> {
+    let (addr, mut peers_rx, shutdown_tx) = start_listener(EncryptionPolicy::Disabled).await?;
+
+    let mut remote = Peer::new(addr, INFO_HASH, REMOTE_PEER_ID.parse().unwrap());
+    remote.handshake().await?;
+    assert_eq!(
+        remote.remote_peer_id(),
+        Some(OUR_PEER_ID.as_bytes().try_into()?)
+    );
+
+    let accepted = peers_rx.recv().await.expect("Listener accepts the peer");
+    assert_eq!(
+        accepted.remote_peer_id(),
+        Some(REMOTE_PEER_ID.as_bytes().try_into()?)
+    );
+
+    shutdown_tx.send(())?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_accepted_peer_exchanges_messages() -> anyhow::Result<()> {
+    let (addr, mut peers_rx, shutdown_tx) = start_listener(EncryptionPolicy::Disabled).await?;
+
+    let mut remote = Peer::new(addr, INFO_HASH, REMOTE_PEER_ID.parse().unwrap());
+    let remote_task = tokio::spawn(async move {
+        let bitfield = remote.connect(TOTAL_PIECES).await?;
+        let pieces: Vec<usize> = bitfield.pieces().collect();
+        remote.send_message(PeerMessage::Interested).await?;
+        anyhow::Ok(pieces)
+    });
+
+    let mut accepted = peers_rx.recv().await.expect("Listener accepts the peer");
+    let mut ours = Bitfield::new(TOTAL_PIECES);
+    ours.set_piece(2);
+    accepted.send_bitfield(&ours).await?;
+    assert_eq!(accepted.receive_message().await?, PeerMessage::Interested);
+
+    assert_eq!(remote_task.await??, vec![2]);
+
+    shutdown_tx.send(())?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_inbound_handshake_wrong_info_hash() -> anyhow::Result<()> {
+    let (addr, mut peers_rx, shutdown_tx) = start_listener(EncryptionPolicy::Disabled).await?;
+
+    let mut remote = Peer::new(addr, [4; 20].into(), REMOTE_PEER_ID.parse().unwrap());
+    assert!(remote.handshake().await.is_err());
+
+    shutdown_tx.send(())?;
+    assert!(peers_rx.recv().await.is_none());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_inbound_encrypted_connection() -> anyhow::Result<()> {
+    let (addr, mut peers_rx, shutdown_tx) = start_listener(EncryptionPolicy::Require).await?;
+
+    let mut remote = Peer::new(addr, INFO_HASH, REMOTE_PEER_ID.parse().unwrap())
+        .with_encryption(EncryptionPolicy::Require);
+    let remote_task = tokio::spawn(async move {
+        remote.connect(TOTAL_PIECES).await?;
+        remote.send_message(PeerMessage::Interested).await?;
+        anyhow::Ok(())
+    });
+
+    let mut accepted = peers_rx.recv().await.expect("Listener accepts the peer");
+    accepted
+        .send_bitfield(&Bitfield::full(TOTAL_PIECES))
+        .await?;
+    assert_eq!(accepted.receive_message().await?, PeerMessage::Interested);
+    remote_task.await??;
+
+    shutdown_tx.send(())?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_inbound_plaintext_under_prefer() -> anyhow::Result<()> {
+    let (addr, mut peers_rx, shutdown_tx) = start_listener(EncryptionPolicy::Prefer).await?;
+
+    let mut remote = Peer::new(addr, INFO_HASH, REMOTE_PEER_ID.parse().unwrap());
+    remote.handshake().await?;
+    assert!(peers_rx.recv().await.is_some());
+
+    shutdown_tx.send(())?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_inbound_plaintext_under_require() -> anyhow::Result<()> {
+    let (addr, mut peers_rx, shutdown_tx) = start_listener(EncryptionPolicy::Require).await?;
+
+    let mut remote = Peer::new(addr, INFO_HASH, REMOTE_PEER_ID.parse().unwrap());
+    assert!(remote.handshake().await.is_err());
+
+    shutdown_tx.send(())?;
+    assert!(peers_rx.recv().await.is_none());
+    Ok(())
+}
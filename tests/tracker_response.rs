@@ -0,0 +1,22 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use torrent_rs::peer::PeerAddresses;
+use torrent_rs::tracker::TrackerResponse;
+
+#[test]
+fn test_tracker_response_uses_peer_addresses() {
+    let mut body = Vec::new();
+    body.extend_from_slice(b"d8:intervali1800e5:peers6:");
+    body.extend_from_slice(&[10, 0, 0, 1, 0x1A, 0xE1]);
+    body.extend_from_slice(b"e");
+
+    let response: TrackerResponse = serde_bencode::from_bytes(&body).unwrap();
+    let peers: PeerAddresses = response.peer_addresses;
+
+    assert_eq!(
+        peers,
+        PeerAddresses(vec![SocketAddr::new(
+            Ipv4Addr::new(10, 0, 0, 1).into(),
+            6881
+        )])
+    );
+}
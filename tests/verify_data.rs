@@ -0,0 +1,54 @@
+use torrent_rs::piece::verify_all;
+use torrent_rs::torrent::Torrent;
+
+const ANNOUNCE: &str = "http://tracker.test/announce";
+
+/// Content of 3 + 5 + 10 bytes in pieces of 4, so pieces 0 and 1 straddle file boundaries and
+/// the last piece is 2 bytes.
+async fn create_content() -> anyhow::Result<(tempfile::TempDir, Torrent)> {
+    let root = tempfile::tempdir()?;
+    let content = root.path().join("content");
+    std::fs::create_dir_all(content.join("sub"))?;
+    std::fs::write(content.join("a.txt"), vec![1u8; 3])?;
+    std::fs::write(content.join("b.txt"), vec![2u8; 5])?;
+    std::fs::write(content.join("sub").join("c.txt"), vec![3u8; 10])?;
+
+    let torrent = Torrent::create(&content, 4, ANNOUNCE).await?;
+    Ok((root, torrent))
+}
+
+#[tokio::test]
+async fn test_verify_intact_content() -> anyhow::Result<()> {
+    let (root, torrent) = create_content().await?;
+
+    let report = verify_all(&torrent, root.path()).await?;
+    assert_eq!(report.good, vec![0, 1, 2, 3, 4]);
+    assert!(report.is_complete());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_verify_corrupted_piece() -> anyhow::Result<()> {
+    let (root, torrent) = create_content().await?;
+
+    // Byte 6 of the content is in b.txt, inside piece 1
+    let b = root.path().join("content").join("b.txt");
+    std::fs::write(&b, [2, 2, 2, 9, 2])?;
+
+    let report = verify_all(&torrent, root.path()).await?;
+    assert_eq!(report.bad, vec![1]);
+    assert_eq!(report.good, vec![0, 2, 3, 4]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_verify_missing_file() -> anyhow::Result<()> {
+    let (root, torrent) = create_content().await?;
+    std::fs::remove_file(root.path().join("content").join("sub").join("c.txt"))?;
+
+    // c.txt covers bytes 8..18, pieces 2 to 4
+    let report = verify_all(&torrent, root.path()).await?;
+    assert_eq!(report.good, vec![0, 1]);
+    assert_eq!(report.missing, vec![2, 3, 4]);
+    Ok(())
+}